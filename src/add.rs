@@ -1,29 +1,18 @@
 use crate::{
-    config::{self, DotConfig},
+    config::{self, DotConfig, DotEntry},
+    context::Context,
+    plan::{ApplyError, Operation, Plan},
     types::{LinkPath, ProjectPath, SourcePath},
     utils::AbsPath,
     CONFIG_FILE_NAME,
 };
-use std::{
-    fs, io,
-    os::unix::fs as unix_fs,
-    path::{Path, PathBuf},
-};
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum AddError {
-    #[error(
-        "unexpected IO error while adding dotfile, successfully rolled-back changes\n IO-Error: {0}"
-    )]
-    IO(#[from] io::Error),
-    #[error(
-        "unexpected IO error while adding dotfile, could not roll-back changes\n io-error: {original_error}\n rollback-error: {rollback_error}"
-    )]
-    RollbackError {
-        original_error: io::Error,
-        rollback_error: io::Error,
-    },
+    #[error("could not add dotfile: {0}")]
+    Apply(#[from] ApplyError),
     #[error("no source file or folder found at {0}")]
     SourceNotFound(PathBuf),
     #[error("target {0} already exists in project")]
@@ -38,52 +27,12 @@ pub enum AddError {
     DotfileRecordExists(PathBuf),
 }
 
-fn raw_add(
-    source: &Path,
-    full_target: &Path,
-    config_path: &Path,
-    config_content: &str,
-) -> Result<(), io::Error> {
-    fs::rename(source, full_target)?;
-    unix_fs::symlink(&full_target, &source)?;
-    fs::write(config_path, config_content)?;
-    return Ok(());
-}
-
-fn rollback_add(source: &Path, target: &Path) -> Result<(), io::Error> {
-    if source.exists() && source.is_symlink() {
-        fs::remove_file(source)?;
-    }
-    if target.exists() && !source.exists() {
-        fs::rename(target, source)?;
-    }
-    Ok(())
-}
-fn atomic_add(
-    source: &Path,
-    target: &Path,
-    config: &Path,
-    config_content: &str,
-) -> Result<(), AddError> {
-    let result = raw_add(source, target, config, config_content);
-    if let Err(err) = result {
-        if let Err(rollback_error) = rollback_add(source, target) {
-            return Err(AddError::RollbackError {
-                original_error: err,
-                rollback_error,
-            });
-        }
-        return Err(AddError::IO(err));
-    }
-    return Ok(());
-}
-
-fn add_home_dotfile(
+fn plan_home_dotfile(
     home: &AbsPath,
     link: &LinkPath,
     project: &ProjectPath,
     target: &SourcePath,
-) -> Result<(), AddError> {
+) -> Result<Plan, AddError> {
     let abs_source = home.join(link);
     let abs_target = project.join(target);
     if !abs_source.exists() {
@@ -100,22 +49,31 @@ fn add_home_dotfile(
     if config.dotfiles.contains_key(target) {
         return Err(AddError::DotfileRecordExists(target.to_path_buf()));
     }
-    if let Some(parent) = abs_target.parent() {
-        fs::create_dir_all(parent)?;
-    }
-    let _ = config.dotfiles.insert(target.clone(), link.clone());
-    let config_content = config.to_string()?;
-    atomic_add(&abs_source, &abs_target, &abs_config, &config_content)?;
-    Ok(())
+    let _ = config
+        .dotfiles
+        .insert(target.clone(), DotEntry::new(link.clone()));
+    let mut plan = Plan::journaled(project);
+    plan.create_parents(&abs_target);
+    plan.push(Operation::Move {
+        from: abs_source.clone(),
+        to: abs_target.clone(),
+    });
+    plan.push(Operation::Symlink {
+        link: abs_source,
+        target: abs_target,
+    });
+    plan.write_config(&abs_config, &config)?;
+    Ok(plan)
 }
 
-pub fn add(
-    home: &AbsPath,
-    link: &LinkPath,
-    project: &ProjectPath,
-    target: &SourcePath,
-) -> Result<(), AddError> {
-    add_home_dotfile(home, link, project, target)
+/// Plan moving the dotfile at `link` into the project as `target`.
+pub fn plan_add(ctx: &Context, link: &LinkPath, target: &SourcePath) -> Result<Plan, AddError> {
+    plan_home_dotfile(ctx.home(), link, ctx.project(), target)
+}
+
+pub fn add(ctx: &Context, link: &LinkPath, target: &SourcePath) -> Result<(), AddError> {
+    plan_add(ctx, link, target)?.apply()?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -129,23 +87,22 @@ mod tests {
     use crate::tests::root_dir;
 
     #[rstest]
-    fn basic_add(root_dir: &PathBuf) {
+    fn basic_add(root_dir: &AbsPath) {
         let test_dir = AbsPath::new(root_dir.join("basic_add")).unwrap();
         create_dir(&test_dir).expect("Could not create test directory.");
         let f = get_example_structure(&test_dir, &test_dir, &test_dir);
         example_new_user_from_structure(&f).expect("Could not setup folder structure.");
-        init::init_project(&f.dotfiles).unwrap();
+        let ctx = f.context();
+        init::init_project(&ctx).unwrap();
         dbg!(&f);
-        add(&f.home, &f.bashrc.link, &f.dotfiles, &f.bashrc.source)
-            .expect("Could not add bashrc to target.");
+        add(&ctx, &f.bashrc.link, &f.bashrc.source).expect("Could not add bashrc to target.");
         assert!(&f.home.join(&f.bashrc.link).is_symlink());
         assert!(&f.dotfiles.join(&f.bashrc.source).exists());
         assert!(!f.home.join(&f.nvim.link).is_symlink());
         assert!(f.home.join(&f.nvim.link).exists());
         assert!(!f.dotfiles.join(&f.nvim.source).exists());
         assert!(!f.dotfiles.join(&f.nvim.source).join("init.lua").exists());
-        add(&test_dir, &f.nvim.link, &f.dotfiles, &f.nvim.source)
-            .expect("Could not add bashrc to target.");
+        add(&ctx, &f.nvim.link, &f.nvim.source).expect("Could not add bashrc to target.");
         assert!(f.home.join(&f.nvim.link).is_symlink());
         assert!(f.dotfiles.join(&f.nvim.source).exists());
         assert!(f.dotfiles.join(&f.nvim.source).join("init.lua").exists());
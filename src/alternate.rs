@@ -0,0 +1,256 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{context::Context, machine::Machine, types::SourcePath};
+
+/// Separates the name of a dotfile from the conditions of one of its
+/// alternates, as in `bashrc##host.laptop`.
+pub const ALTERNATE_SEPARATOR: &str = "##";
+
+/// Single condition in the name of an alternate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// `host.<name>`, the hostname is `name`.
+    Host(String),
+    /// `profile.<name>`, the profile `name` is active.
+    Profile(String),
+    /// `os.<name>`, the operating system is `name`.
+    Os(String),
+    /// `default`, always holds.
+    Default,
+}
+
+impl Selector {
+    fn parse(s: &str) -> Option<Self> {
+        if s == "default" {
+            return Some(Selector::Default);
+        }
+        let (kind, value) = s.split_once('.')?;
+        let value = value.to_string();
+        match kind {
+            "host" | "hostname" => Some(Selector::Host(value)),
+            "profile" => Some(Selector::Profile(value)),
+            "os" => Some(Selector::Os(value)),
+            _ => None,
+        }
+    }
+
+    /// How specific the selector is, the matching alternate with the highest total is used.
+    fn weight(&self) -> u32 {
+        match self {
+            Selector::Host(_) => 4,
+            Selector::Profile(_) => 2,
+            Selector::Os(_) => 1,
+            Selector::Default => 0,
+        }
+    }
+
+    fn holds(&self, machine: &Machine) -> bool {
+        match self {
+            Selector::Host(host) => *host == machine.hostname,
+            Selector::Profile(profile) => machine.profiles.contains(profile),
+            Selector::Os(os) => *os == machine.os,
+            Selector::Default => true,
+        }
+    }
+}
+
+/// Candidate source of a dotfile, next to where the source itself would be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alternate {
+    pub path: PathBuf,
+    pub selectors: Vec<Selector>,
+}
+
+impl Alternate {
+    /// Parse `path`, if it is an alternate of `abs_source`.
+    fn parse(abs_source: &Path, path: PathBuf) -> Option<Self> {
+        let name = abs_source.file_name()?.to_str()?;
+        let file_name = path.file_name()?.to_str()?;
+        let conditions = file_name
+            .strip_prefix(name)?
+            .strip_prefix(ALTERNATE_SEPARATOR)?;
+        let selectors = conditions
+            .split(',')
+            .map(Selector::parse)
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { path, selectors })
+    }
+
+    pub fn holds(&self, machine: &Machine) -> bool {
+        self.selectors.iter().all(|s| s.holds(machine))
+    }
+
+    fn weight(&self) -> u32 {
+        self.selectors.iter().map(Selector::weight).sum()
+    }
+}
+
+/// Every alternate of `abs_source`, sorted by file name.
+///
+/// Names with conditions that are not understood are ignored, as is a
+/// parent directory that cannot be read.
+pub fn alternates(abs_source: &Path) -> Vec<Alternate> {
+    let Some(Ok(entries)) = abs_source.parent().map(fs::read_dir) else {
+        return vec![];
+    };
+    let mut alternates: Vec<_> = entries
+        .filter_map(|e| Alternate::parse(abs_source, e.ok()?.path()))
+        .collect();
+    alternates.sort_by(|a, b| a.path.cmp(&b.path));
+    alternates
+}
+
+/// Source to use for `abs_source` on `machine`.
+///
+/// The source itself wins if it exists. Otherwise the matching alternate with
+/// the most specific conditions is used, ties going to the first by file name.
+/// `None` if neither is found.
+pub fn select(abs_source: &Path, machine: &Machine) -> Option<PathBuf> {
+    if abs_source.exists() || abs_source.is_symlink() {
+        return Some(abs_source.to_path_buf());
+    }
+    let mut best: Option<Alternate> = None;
+    for alternate in alternates(abs_source) {
+        if alternate.holds(machine)
+            && best
+                .as_ref()
+                .is_none_or(|b| alternate.weight() > b.weight())
+        {
+            best = Some(alternate);
+        }
+    }
+    best.map(|a| a.path)
+}
+
+/// Source of the recorded `source` in use on this machine, see [`select`].
+pub fn active_source(ctx: &Context, source: &SourcePath) -> Option<PathBuf> {
+    select(&ctx.project().join(source), &Machine::current(ctx))
+}
+
+/// The source itself, if it exists, followed by all of its alternates.
+pub fn source_family(abs_source: &Path) -> Vec<PathBuf> {
+    let plain = (abs_source.exists() || abs_source.is_symlink()).then(|| abs_source.to_path_buf());
+    plain
+        .into_iter()
+        .chain(alternates(abs_source).into_iter().map(|a| a.path))
+        .collect()
+}
+
+/// Where `path`, the source `abs_source` or one of its alternates, ends up
+/// when the source is renamed to `new_source`.
+pub fn renamed(path: &Path, abs_source: &Path, new_source: &Path) -> Option<PathBuf> {
+    if path == abs_source {
+        return Some(new_source.to_path_buf());
+    }
+    let name = abs_source.file_name()?.to_str()?;
+    let suffix = path.file_name()?.to_str()?.strip_prefix(name)?;
+    let new_name = new_source.file_name()?.to_str()?;
+    Some(new_source.with_file_name(format!("{new_name}{suffix}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        diff::project_diff,
+        example::{example_new_machine_from_structure, get_example_structure},
+        heal::find_replaced,
+        mv::move_dotfile,
+        remove::{remove, RemoveMode},
+        setup,
+        tests::root_dir,
+        types::LinkPath,
+        update::update,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+
+    #[rstest]
+    fn select_alternate(root_dir: &AbsPath) {
+        let test_dir = root_dir.join("select_alternate");
+        fs::create_dir_all(&test_dir).unwrap();
+        for name in [
+            "bashrc##default",
+            "bashrc##os.linux",
+            "bashrc##host.laptop",
+            "bashrc##os.linux,profile.work",
+            "bashrc##arch.x86",
+            "bashrc.bak",
+        ] {
+            fs::write(test_dir.join(name), "").unwrap();
+        }
+        let source = test_dir.join("bashrc");
+        assert_eq!(alternates(&source).len(), 4);
+        let mut machine = Machine {
+            hostname: "desktop".to_string(),
+            os: "linux".to_string(),
+            profiles: vec![],
+        };
+        assert_eq!(
+            select(&source, &machine),
+            Some(test_dir.join("bashrc##os.linux"))
+        );
+        machine.profiles.push("work".to_string());
+        assert_eq!(
+            select(&source, &machine),
+            Some(test_dir.join("bashrc##os.linux,profile.work"))
+        );
+        machine.hostname = "laptop".to_string();
+        assert_eq!(
+            select(&source, &machine),
+            Some(test_dir.join("bashrc##host.laptop"))
+        );
+        machine = Machine {
+            hostname: "server".to_string(),
+            os: "freebsd".to_string(),
+            profiles: vec![],
+        };
+        assert_eq!(
+            select(&source, &machine),
+            Some(test_dir.join("bashrc##default"))
+        );
+        fs::write(&source, "").unwrap();
+        assert_eq!(select(&source, &machine), Some(source.clone()));
+        assert_eq!(select(&test_dir.join("zshrc"), &machine), None);
+    }
+
+    #[rstest]
+    fn commands_use_active_alternate(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("commands_use_active_alternate")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let plain = f.dotfiles.join(&f.bashrc.source);
+        let default = plain.with_file_name("bashrc##default");
+        fs::rename(&plain, &default).unwrap();
+        setup::setup_project(&ctx, Default::default()).unwrap();
+        let link = f.home.join(&f.bashrc.link);
+        assert_eq!(fs::read_link(&link).unwrap(), default);
+        assert!(project_diff(&ctx, Some(&f.bashrc.source))
+            .unwrap()
+            .is_empty());
+
+        fs::remove_file(&link).unwrap();
+        fs::write(&link, "replaced").unwrap();
+        assert_eq!(find_replaced(&ctx, None).unwrap().len(), 1);
+        fs::remove_file(&link).unwrap();
+
+        let new_link = LinkPath::new(".bashrc").unwrap();
+        update(&ctx, &new_link, &f.bashrc.source).unwrap();
+        let link = f.home.join(&new_link);
+        assert_eq!(fs::read_link(&link).unwrap(), default);
+
+        let to = SourcePath::new("shell/bashrc").unwrap();
+        move_dotfile(&ctx, &f.bashrc.source, &to).unwrap();
+        let moved = f.dotfiles.join("shell/bashrc##default");
+        assert!(moved.is_file() && !default.exists());
+        assert_eq!(fs::read_link(&link).unwrap(), moved);
+
+        remove(&ctx, &to, RemoveMode::Source).unwrap();
+        assert!(!link.is_symlink());
+        assert!(!moved.exists());
+    }
+}
@@ -4,7 +4,13 @@ use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
-pub struct CLI {
+pub struct Cli {
+    /// Home directory to link dotfiles into, defaults to $DOTMAN_HOME or $HOME
+    #[arg(long, global = true)]
+    pub home: Option<PathBuf>,
+    /// Profile to activate, can be repeated, defaults to the comma separated $DOTMAN_PROFILE
+    #[arg(long, global = true)]
+    pub profile: Vec<String>,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -19,10 +25,25 @@ pub enum Commands {
     Setup(SetupArgs),
     /// Show status of dotman project
     Status(StatusArgs),
+    /// List dotfiles recorded in dotman project
+    List(ListArgs),
     /// Update links in dotman project
     Update(UpdateArgs),
     /// Restore dotfiles
     Restore(RestoreArgs),
+    /// Finish or revert an interrupted operation
+    Recover(RecoverArgs),
+    /// Stop tracking a dotfile
+    #[command(alias = "forget")]
+    Remove(RemoveArgs),
+    /// Rename a dotfile inside the project
+    Mv(MvArgs),
+    /// Re-link dotfiles whose link was replaced by a regular file
+    Heal(HealArgs),
+    /// Show how the files at the link paths differ from the project
+    Diff(DiffArgs),
+    /// Bring dotfiles in copy mode in sync
+    Sync(SyncArgs),
     /// Create example file structure
     Example(ExampleArgs),
 }
@@ -65,6 +86,9 @@ pub struct AddArgs {
     /// Name of moved dotfile, defaults to dotfile name.
     #[arg(short, long)]
     pub target: Option<PathBuf>,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -75,6 +99,28 @@ pub struct SetupArgs {
     /// Dotfile to setup, defaults to all dotfiles in project
     #[arg(short, long)]
     pub dotfile: Option<PathBuf>,
+    /// What to do with files already in place of a link
+    #[arg(long, value_enum, default_value_t = OnConflict::Fail)]
+    pub on_conflict: OnConflict,
+    #[command(flatten)]
+    pub tags: TagArgs,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum OnConflict {
+    /// Fail without changing anything
+    Fail,
+    /// Leave the dotfile unlinked
+    Skip,
+    /// Move the existing file to a timestamped backup in the project
+    Backup,
+    /// Delete the existing file
+    Overwrite,
+    /// Move the existing file into the project as the new source
+    Adopt,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -85,6 +131,41 @@ pub struct StatusArgs {
     /// Look for dotman project recursively
     #[arg(short, long)]
     pub recursive: bool,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = StatusFormat::Human)]
+    pub format: StatusFormat,
+    #[command(flatten)]
+    pub tags: TagArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ListArgs {
+    /// Project to list dotfiles of
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    #[command(flatten)]
+    pub tags: TagArgs,
+}
+
+/// Restricts a command on the whole project to some of its dotfiles.
+#[derive(Args, Debug, Clone)]
+pub struct TagArgs {
+    /// Only consider dotfiles with this tag, can be repeated
+    #[arg(long = "tag")]
+    pub include: Vec<String>,
+    /// Leave out dotfiles with this tag, can be repeated
+    #[arg(long = "exclude-tag")]
+    pub exclude: Vec<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFormat {
+    /// Readable summary per project
+    Human,
+    /// JSON object per project, or an array of them with `--recursive`
+    Json,
+    /// Tab separated line per dotfile
+    Porcelain,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -92,11 +173,133 @@ pub struct RestoreArgs {
     /// Project to show status of
     #[arg(default_value = ".")]
     pub project: PathBuf,
-    /// Dotfile to setup, defaults to all dotfiles in project
+    /// Dotfile to restore, defaults to all dotfiles in project
+    #[arg(short, long)]
+    pub dotfile: Option<PathBuf>,
+    /// Keep restored dotfiles recorded in the project config
+    #[arg(long)]
+    pub keep_record: bool,
+    /// Replace links with copies and leave the project untouched
+    #[arg(long)]
+    pub copy: bool,
+    #[command(flatten)]
+    pub tags: TagArgs,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RemoveArgs {
+    /// Dotfile to stop tracking
+    pub dotfile: PathBuf,
+    /// Project of dotfile
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    /// What to remove besides the config record
+    #[arg(short, long, value_enum, default_value_t = RemoveMode::Record)]
+    pub mode: RemoveMode,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct MvArgs {
+    /// Dotfile to rename
+    pub from: PathBuf,
+    /// New path of the dotfile in the project
+    pub to: PathBuf,
+    /// Project of dotfile
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SyncArgs {
+    /// Which side to overwrite with the other
+    #[arg(value_enum)]
+    pub direction: SyncDirection,
+    /// Project to sync
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    /// Dotfile to sync, defaults to all dotfiles in copy mode
+    #[arg(short, long)]
+    pub dotfile: Option<PathBuf>,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum SyncDirection {
+    /// Copy the project sources over the copies in the home directory
+    ToHome,
+    /// Copy the copies in the home directory over the project sources
+    ToProject,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DiffArgs {
+    /// Project to compare
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    /// Dotfile to compare, defaults to all dotfiles in project
     #[arg(short, long)]
     pub dotfile: Option<PathBuf>,
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct HealArgs {
+    /// Project to heal
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    /// Dotfile to heal, defaults to all dotfiles in project
+    #[arg(short, long)]
+    pub dotfile: Option<PathBuf>,
+    /// Which copy of a replaced dotfile to keep
+    #[arg(long, value_enum, default_value_t = HealPolicy::Ask)]
+    pub policy: HealPolicy,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum HealPolicy {
+    /// Show the diff and ask for every dotfile
+    Ask,
+    /// Keep whichever copy was modified last
+    Newer,
+    /// Keep the copy in the home directory
+    Home,
+    /// Keep the copy in the project
+    Project,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum RemoveMode {
+    /// Only drop the record from the project config
+    Record,
+    /// Also delete the symlink in the home directory
+    Link,
+    /// Also delete the source from the project
+    Source,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RecoverArgs {
+    /// Project with the interrupted operation
+    #[arg(default_value = ".")]
+    pub project: PathBuf,
+    /// Apply the remaining steps instead of reverting the applied ones
+    #[arg(long)]
+    pub finish: bool,
+}
+
 #[derive(Args, Debug, Clone)]
 pub struct UpdateArgs {
     /// Dofile to update
@@ -106,4 +309,7 @@ pub struct UpdateArgs {
     /// Project of dotfile
     #[arg(default_value = ".")]
     pub project: PathBuf,
+    /// Print the planned changes without applying them
+    #[arg(long)]
+    pub dry_run: bool,
 }
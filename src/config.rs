@@ -1,19 +1,239 @@
-use std::{collections::BTreeMap, fs, io, path::Path};
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
 
 use thiserror::Error;
 
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, value::MapAccessDeserializer, IntoDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 use toml;
 
 use crate::types::{LinkPath, SourcePath};
 
-type DotItems = BTreeMap<SourcePath, LinkPath>;
+type DotItems = BTreeMap<SourcePath, DotEntry>;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct DotConfig {
     pub dotfiles: DotItems,
 }
 
+/// How a dotfile is put in place at its link path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Symlink pointing at the project source.
+    #[default]
+    Symlink,
+    /// Copy of the project source, kept in sync with `dotman sync`.
+    Copy,
+    /// Hard link to the project source, only for files on the same filesystem.
+    Hardlink,
+    /// File rendered from the project source with the template variables.
+    Template,
+}
+
+impl Mode {
+    fn is_default(&self) -> bool {
+        *self == Mode::default()
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Mode::Symlink => "symlink",
+            Mode::Copy => "copy",
+            Mode::Hardlink => "hardlink",
+            Mode::Template => "template",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Machines a dotfile applies to, every condition that is set has to hold.
+///
+/// Each condition lists alternatives, of which one has to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// One of these profiles is active.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    /// The hostname is one of these.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hostnames: Vec<String>,
+    /// The operating system, as named by Rust, for example `linux` or `macos`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+    /// Environment variables, `NAME` when set and not empty, `NAME=value` when set to `value`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+}
+
+impl Condition {
+    pub fn is_empty(&self) -> bool {
+        *self == Condition::default()
+    }
+}
+
+/// Restricts an operation to the dotfiles with some tags, or without others.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl TagFilter {
+    /// Whether `entry` has one of the included tags, if any, and none of the excluded ones.
+    pub fn matches(&self, entry: &DotEntry) -> bool {
+        let tagged = |tags: &[String]| tags.iter().any(|t| entry.tags.contains(t));
+        (self.include.is_empty() || tagged(&self.include)) && !tagged(&self.exclude)
+    }
+}
+
+/// A single recorded dotfile.
+///
+/// Written as just the link path, `bashrc = "~/.bashrc"`, unless a setting
+/// differs from its default or the entry was read as a table, in which case
+/// it is written as a table.
+#[derive(Debug, Clone, Eq, Deserialize, Serialize)]
+#[serde(from = "RawEntry", into = "RawEntry")]
+pub struct DotEntry {
+    pub link: LinkPath,
+    pub mode: Mode,
+    pub tags: Vec<String>,
+    pub when: Condition,
+    /// Keep the table form even when every setting has its default.
+    table: bool,
+}
+
+impl PartialEq for DotEntry {
+    /// Entries are equal when their settings are, however they are written.
+    fn eq(&self, other: &Self) -> bool {
+        self.link == other.link
+            && self.mode == other.mode
+            && self.tags == other.tags
+            && self.when == other.when
+    }
+}
+
+impl DotEntry {
+    pub fn new(link: LinkPath) -> Self {
+        Self {
+            link,
+            mode: Mode::default(),
+            tags: vec![],
+            when: Condition::default(),
+            table: false,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn with_condition(mut self, when: Condition) -> Self {
+        self.when = when;
+        self
+    }
+
+    /// Whether every setting besides the link has its default.
+    pub fn is_shorthand(&self) -> bool {
+        self.mode.is_default() && self.tags.is_empty() && self.when.is_empty()
+    }
+}
+
+/// Table form of an entry, `[dotfiles.bashrc]` followed by its settings.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct EntryTable {
+    link: LinkPath,
+    #[serde(default, skip_serializing_if = "Mode::is_default")]
+    mode: Mode,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Condition::is_empty")]
+    when: Condition,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RawEntry {
+    Link(LinkPath),
+    Table(EntryTable),
+}
+
+// Deserialized by hand instead of `untagged`, to report what is wrong inside
+// a table rather than that it matches neither form.
+impl<'de> Deserialize<'de> for RawEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RawEntryVisitor;
+
+        impl<'de> Visitor<'de> for RawEntryVisitor {
+            type Value = RawEntry;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a link path or a table with a `link`")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                LinkPath::deserialize(value.into_deserializer()).map(RawEntry::Link)
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                EntryTable::deserialize(MapAccessDeserializer::new(map)).map(RawEntry::Table)
+            }
+        }
+
+        deserializer.deserialize_any(RawEntryVisitor)
+    }
+}
+
+impl From<RawEntry> for DotEntry {
+    fn from(raw: RawEntry) -> Self {
+        match raw {
+            RawEntry::Link(link) => DotEntry::new(link),
+            RawEntry::Table(table) => DotEntry {
+                link: table.link,
+                mode: table.mode,
+                tags: table.tags,
+                when: table.when,
+                table: true,
+            },
+        }
+    }
+}
+
+impl From<DotEntry> for RawEntry {
+    fn from(entry: DotEntry) -> Self {
+        if entry.is_shorthand() && !entry.table {
+            RawEntry::Link(entry.link)
+        } else {
+            RawEntry::Table(EntryTable {
+                link: entry.link,
+                mode: entry.mode,
+                tags: entry.tags,
+                when: entry.when,
+            })
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum WriteError {
     #[error("Could not serialize dotfile config due to: {0}")]
@@ -43,13 +263,13 @@ impl DotConfig {
     pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), WriteError> {
         let config_str = self.to_string()?;
         fs::write(path.as_ref(), config_str)?;
-        return Ok(());
+        Ok(())
     }
 
     pub fn from_file<P: AsRef<Path>>(config_path: P) -> Result<Self, ReadError> {
         let toml_content = fs::read_to_string(config_path)?;
         let config: DotConfig = toml::from_str(&toml_content)?;
-        return Ok(config);
+        Ok(config)
     }
 }
 
@@ -63,15 +283,15 @@ mod tests {
             dotfiles: DotItems::from([
                 (
                     SourcePath::try_from("B").unwrap(),
-                    LinkPath::try_from("a/b/c").unwrap(),
+                    DotEntry::new(LinkPath::try_from("a/b/c").unwrap()),
                 ),
                 (
                     SourcePath::try_from("A").unwrap(),
-                    LinkPath::try_from("a").unwrap(),
+                    DotEntry::new(LinkPath::try_from("a").unwrap()),
                 ),
                 (
                     SourcePath::try_from("a/b").unwrap(),
-                    LinkPath::try_from("a/b").unwrap(),
+                    DotEntry::new(LinkPath::try_from("a/b").unwrap()),
                 ),
             ]),
         };
@@ -96,19 +316,85 @@ B = "~/a/b/c"
             dotfiles: DotItems::from([
                 (
                     SourcePath::try_from("B").unwrap(),
-                    LinkPath::try_from("a/b/c").unwrap(),
+                    DotEntry::new(LinkPath::try_from("a/b/c").unwrap()),
                 ),
                 (
                     SourcePath::try_from("A").unwrap(),
-                    LinkPath::try_from("a").unwrap(),
+                    DotEntry::new(LinkPath::try_from("a").unwrap()),
                 ),
                 (
                     SourcePath::try_from("a/b").unwrap(),
-                    LinkPath::try_from("a/b").unwrap(),
+                    DotEntry::new(LinkPath::try_from("a/b").unwrap()),
                 ),
             ]),
         };
         let actual: DotConfig = toml::from_str(toml_content).unwrap();
         assert_eq!(actual, expected_config);
     }
+
+    #[test]
+    fn test_mode_table() {
+        let toml_content = r#"[dotfiles]
+bashrc = "~/.bashrc"
+
+[dotfiles.ssh_config]
+link = "~/.ssh/config"
+mode = "copy"
+"#;
+        let config: DotConfig = toml::from_str(toml_content).unwrap();
+        let ssh_config = &config.dotfiles[&SourcePath::try_from("ssh_config").unwrap()];
+        assert_eq!(ssh_config.mode, Mode::Copy);
+        assert_eq!(config.to_string().unwrap(), toml_content);
+    }
+
+    #[test]
+    fn test_condition_table() {
+        let toml_content = r#"[dotfiles.syncthing]
+link = "~/.config/syncthing"
+
+[dotfiles.syncthing.when]
+profiles = ["personal"]
+os = ["linux"]
+"#;
+        let config: DotConfig = toml::from_str(toml_content).unwrap();
+        let syncthing = &config.dotfiles[&SourcePath::try_from("syncthing").unwrap()];
+        assert_eq!(syncthing.mode, Mode::Symlink);
+        assert_eq!(syncthing.when.profiles, vec!["personal".to_string()]);
+        assert!(syncthing.when.hostnames.is_empty());
+        assert_eq!(config.to_string().unwrap(), toml_content);
+    }
+
+    #[test]
+    fn test_entry_forms_round_trip() {
+        let toml_content = r#"[dotfiles]
+bashrc = "~/.bashrc"
+
+[dotfiles.gitconfig]
+link = "~/.gitconfig"
+
+[dotfiles.nvim]
+link = "~/.config/nvim"
+tags = ["editor"]
+"#;
+        let mut config: DotConfig = toml::from_str(toml_content).unwrap();
+        let gitconfig = SourcePath::try_from("gitconfig").unwrap();
+        assert!(config.dotfiles[&gitconfig].is_shorthand());
+        assert_eq!(
+            config.dotfiles[&gitconfig],
+            DotEntry::new(LinkPath::try_from(".gitconfig").unwrap())
+        );
+        assert_eq!(config.to_string().unwrap(), toml_content);
+
+        let bashrc = SourcePath::try_from("bashrc").unwrap();
+        let entry = config.dotfiles.get_mut(&bashrc).unwrap();
+        entry.tags.push("shell".to_string());
+        assert!(config
+            .to_string()
+            .unwrap()
+            .contains("[dotfiles.bashrc]\nlink = \"~/.bashrc\"\ntags = [\"shell\"]\n"));
+
+        let typo = "[dotfiles.bashrc]\nlink = \"~/.bashrc\"\ntag = [\"shell\"]\n";
+        let err = toml::from_str::<DotConfig>(typo).unwrap_err();
+        assert!(err.to_string().contains("unknown field `tag`"));
+    }
 }
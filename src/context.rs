@@ -0,0 +1,211 @@
+use std::{
+    env::{self, current_dir},
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    config::TagFilter,
+    types::{ProjectPath, ProjectPathError},
+    utils::{normalize_path, AbsPath, AbsPathError},
+    DOTMAN_HOME_ENV, DOTMAN_PROFILE_ENV, HOME_ENV,
+};
+
+/// Environment every dotman operation runs in.
+///
+/// Holds the home directory links are resolved against, the directory relative
+/// paths are resolved against, the root of the dotman project, the active
+/// profiles entries can be conditioned on and the tags operations on the whole
+/// project are restricted to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    home: AbsPath,
+    cwd: AbsPath,
+    project: ProjectPath,
+    profiles: Vec<String>,
+    tags: TagFilter,
+}
+
+#[derive(Error, Debug)]
+pub enum ContextError {
+    #[error("no home directory given, set `--home`, $DOTMAN_HOME or $HOME")]
+    HomeNotSet,
+    #[error("could not determine current directory: {0}")]
+    CurrentDir(#[from] io::Error),
+    #[error("invalid home or current directory: {0}")]
+    InvalidDirectory(#[from] AbsPathError),
+    #[error("invalid project directory: {0}")]
+    InvalidProject(#[from] ProjectPathError),
+}
+
+impl Context {
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+
+    pub fn home(&self) -> &AbsPath {
+        &self.home
+    }
+
+    pub fn cwd(&self) -> &AbsPath {
+        &self.cwd
+    }
+
+    pub fn project(&self) -> &ProjectPath {
+        &self.project
+    }
+
+    pub fn profiles(&self) -> &[String] {
+        &self.profiles
+    }
+
+    pub fn tags(&self) -> &TagFilter {
+        &self.tags
+    }
+
+    /// Same context, but operating on another project.
+    pub fn with_project(&self, project: ProjectPath) -> Self {
+        Self {
+            home: self.home.clone(),
+            cwd: self.cwd.clone(),
+            project,
+            profiles: self.profiles.clone(),
+            tags: self.tags.clone(),
+        }
+    }
+
+    /// Resolve a user supplied path against the home and current directory.
+    pub fn normalize<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        normalize_path(path, &self.home, &self.cwd)
+    }
+}
+
+/// Builds a [`Context`], falling back to the process environment for
+/// everything that is not set explicitly.
+///
+/// The home directory is taken from, in order: [`ContextBuilder::home`],
+/// `$DOTMAN_HOME` and `$HOME`. Without any [`ContextBuilder::profile`], the
+/// comma separated profiles in `$DOTMAN_PROFILE` are active.
+#[derive(Debug, Clone, Default)]
+pub struct ContextBuilder {
+    home: Option<PathBuf>,
+    cwd: Option<PathBuf>,
+    project: Option<PathBuf>,
+    profiles: Vec<String>,
+    tags: TagFilter,
+}
+
+impl ContextBuilder {
+    pub fn home<P: Into<PathBuf>>(mut self, home: P) -> Self {
+        self.home = Some(home.into());
+        self
+    }
+
+    pub fn cwd<P: Into<PathBuf>>(mut self, cwd: P) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Project root, relative paths and `~` are resolved when building.
+    /// Defaults to the current directory.
+    pub fn project<P: Into<PathBuf>>(mut self, project: P) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Activate a profile, can be called several times.
+    pub fn profile<S: Into<String>>(mut self, profile: S) -> Self {
+        self.profiles.push(profile.into());
+        self
+    }
+
+    /// Only consider dotfiles matching `tags` when operating on the whole project.
+    pub fn tags(mut self, tags: TagFilter) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn build(self) -> Result<Context, ContextError> {
+        let cwd = match self.cwd {
+            Some(cwd) => cwd,
+            None => current_dir()?,
+        };
+        let cwd = AbsPath::new(cwd)?;
+        let home = match self.home {
+            Some(home) => home,
+            None => default_home(env::var_os(DOTMAN_HOME_ENV), env::var_os(HOME_ENV))
+                .ok_or(ContextError::HomeNotSet)?,
+        };
+        let home = AbsPath::new(cwd.join(home))?;
+        let project = match self.project {
+            Some(project) => normalize_path(project, &home, &cwd),
+            None => cwd.to_path_buf(),
+        };
+        let project = ProjectPath::new(project)?;
+        let profiles = if self.profiles.is_empty() {
+            env::var(DOTMAN_PROFILE_ENV)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect()
+        } else {
+            self.profiles
+        };
+        Ok(Context {
+            home,
+            cwd,
+            project,
+            profiles,
+            tags: self.tags,
+        })
+    }
+}
+
+/// `dotman_home` if set and not empty, otherwise `home` under the same condition.
+fn default_home(dotman_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let set = |h: &OsString| !h.is_empty();
+    dotman_home
+        .filter(set)
+        .or_else(|| home.filter(set))
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_with_overrides() {
+        let ctx = Context::builder()
+            .home("/h")
+            .cwd("/h/w")
+            .project("~/dotfiles")
+            .build()
+            .unwrap();
+        assert_eq!(ctx.home().as_path(), Path::new("/h"));
+        assert_eq!(ctx.cwd().as_path(), Path::new("/h/w"));
+        assert_eq!(ctx.project().as_path(), Path::new("/h/dotfiles"));
+
+        let ctx = Context::builder()
+            .home("staging")
+            .cwd("/h/w")
+            .build()
+            .unwrap();
+        assert_eq!(ctx.home().as_path(), Path::new("/h/w/staging"));
+        assert_eq!(ctx.project().as_path(), Path::new("/h/w"));
+    }
+
+    #[test]
+    fn test_default_home() {
+        let os = |s: &str| Some(OsString::from(s));
+        assert_eq!(default_home(os("/d"), os("/h")), Some(PathBuf::from("/d")));
+        assert_eq!(default_home(os(""), os("/h")), Some(PathBuf::from("/h")));
+        assert_eq!(default_home(None, os("/h")), Some(PathBuf::from("/h")));
+        assert_eq!(default_home(os(""), os("")), None);
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use similar::TextDiff;
+use thiserror::Error;
+
+use crate::{
+    alternate::active_source,
+    config::{self, DotConfig, Mode},
+    context::Context,
+    machine::Machine,
+    template::{render_file, RenderError, Vars, VarsError},
+    types::SourcePath,
+    CONFIG_FILE_NAME,
+};
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("project not initialized")]
+    ProjectNotInitialized,
+    #[error("dotfile not recorded in config")]
+    DotfileNotRecorded,
+    #[error("could not read dotman config: {0}")]
+    ReadConfigError(#[from] config::ReadError),
+    #[error("could not compare files: {0}")]
+    Compare(#[from] io::Error),
+    #[error("{0}")]
+    Vars(#[from] VarsError),
+    #[error("could not render template: {0}")]
+    Render(#[from] RenderError),
+}
+
+/// Change to a single file inside a directory tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    Removed(PathBuf),
+    Changed(PathBuf),
+    /// Changed file that is not text on at least one side.
+    ChangedBinary(PathBuf),
+}
+
+/// How the copy at the link path differs from the project source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Unified diff between two text files.
+    Text(String),
+    /// Two files differ, but at least one of them is not text.
+    Binary,
+    /// Files added, removed or changed between two directory trees.
+    Dir(Vec<Change>),
+    /// Nothing at the link path.
+    MissingLink,
+    /// Nothing at the source path.
+    MissingSource,
+    /// One side is a file and the other a directory.
+    KindMismatch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DotfileDiff {
+    pub source: SourcePath,
+    pub abs_source: PathBuf,
+    pub abs_link: PathBuf,
+    pub difference: Difference,
+}
+
+impl fmt::Display for DotfileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = self.abs_source.display();
+        let link = self.abs_link.display();
+        match &self.difference {
+            Difference::Text(diff) => write!(f, "{diff}"),
+            Difference::Binary => writeln!(f, "binary files {source} and {link} differ"),
+            Difference::Dir(changes) => {
+                writeln!(f, "directories {source} and {link} differ")?;
+                for change in changes {
+                    match change {
+                        Change::Added(p) => writeln!(f, "  added {}", p.display())?,
+                        Change::Removed(p) => writeln!(f, "  removed {}", p.display())?,
+                        Change::Changed(p) => writeln!(f, "  changed {}", p.display())?,
+                        Change::ChangedBinary(p) => {
+                            writeln!(f, "  changed {} (binary)", p.display())?
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Difference::MissingLink => writeln!(f, "{link} does not exist"),
+            Difference::MissingSource => writeln!(f, "{source} does not exist"),
+            Difference::KindMismatch => {
+                writeln!(f, "{source} and {link} are not both files or directories")
+            }
+        }
+    }
+}
+
+/// Unified diff turning the file at `old` into the one at `new`.
+///
+/// Empty if both have the same content, files that are not valid UTF-8
+/// are only reported as differing binary files.
+pub fn file_diff(old: &Path, new: &Path) -> io::Result<String> {
+    Ok(match compare_files(old, new)? {
+        None => String::new(),
+        Some(Difference::Text(diff)) => diff,
+        Some(_) => format!(
+            "binary files {} and {} differ\n",
+            old.display(),
+            new.display()
+        ),
+    })
+}
+
+fn compare_files(old: &Path, new: &Path) -> io::Result<Option<Difference>> {
+    compare_contents(old, fs::read(old)?, new, fs::read(new)?)
+}
+
+/// Compare `old_content`, standing for the file at `old`, with `new_content` at `new`.
+fn compare_contents(
+    old: &Path,
+    old_content: Vec<u8>,
+    new: &Path,
+    new_content: Vec<u8>,
+) -> io::Result<Option<Difference>> {
+    if old_content == new_content {
+        return Ok(None);
+    }
+    match (
+        String::from_utf8(old_content),
+        String::from_utf8(new_content),
+    ) {
+        (Ok(old_text), Ok(new_text)) => Ok(Some(Difference::Text(
+            TextDiff::from_lines(&old_text, &new_text)
+                .unified_diff()
+                .header(&old.to_string_lossy(), &new.to_string_lossy())
+                .to_string(),
+        ))),
+        _ => Ok(Some(Difference::Binary)),
+    }
+}
+
+/// Paths of every non-directory entry below `dir`, relative to it.
+fn tree_files(dir: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut to_visit = vec![PathBuf::new()];
+    while let Some(rel_dir) = to_visit.pop() {
+        for entry in fs::read_dir(dir.join(&rel_dir))? {
+            let rel_path = rel_dir.join(entry?.file_name());
+            if fs::symlink_metadata(dir.join(&rel_path))?.is_dir() {
+                to_visit.push(rel_path);
+            } else {
+                files.insert(rel_path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn same_entry(old: &Path, new: &Path) -> io::Result<bool> {
+    let old_meta = fs::symlink_metadata(old)?;
+    let new_meta = fs::symlink_metadata(new)?;
+    if old_meta.is_symlink() || new_meta.is_symlink() {
+        return Ok(old_meta.is_symlink()
+            && new_meta.is_symlink()
+            && fs::read_link(old)? == fs::read_link(new)?);
+    }
+    Ok(fs::read(old)? == fs::read(new)?)
+}
+
+/// Whether `path` is a file that is not valid UTF-8.
+fn is_binary(path: &Path) -> io::Result<bool> {
+    if fs::symlink_metadata(path)?.is_symlink() {
+        return Ok(false);
+    }
+    Ok(std::str::from_utf8(&fs::read(path)?).is_err())
+}
+
+fn compare_dirs(old: &Path, new: &Path) -> io::Result<Vec<Change>> {
+    let old_files = tree_files(old)?;
+    let new_files = tree_files(new)?;
+    let mut changes = vec![];
+    for path in old_files.union(&new_files) {
+        match (old_files.contains(path), new_files.contains(path)) {
+            (true, false) => changes.push(Change::Removed(path.clone())),
+            (false, true) => changes.push(Change::Added(path.clone())),
+            _ if !same_entry(&old.join(path), &new.join(path))? => {
+                if is_binary(&old.join(path))? || is_binary(&new.join(path))? {
+                    changes.push(Change::ChangedBinary(path.clone()))
+                } else {
+                    changes.push(Change::Changed(path.clone()))
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(changes)
+}
+
+/// How `new` differs from `old`, `None` if they have the same content.
+pub fn compare(old: &Path, new: &Path) -> io::Result<Option<Difference>> {
+    match (old.exists(), new.exists()) {
+        (false, false) => return Ok(None),
+        (false, true) => return Ok(Some(Difference::MissingSource)),
+        (true, false) => return Ok(Some(Difference::MissingLink)),
+        (true, true) => {}
+    }
+    match (old.is_dir(), new.is_dir()) {
+        (false, false) => compare_files(old, new),
+        (true, true) => {
+            let changes = compare_dirs(old, new)?;
+            Ok((!changes.is_empty()).then_some(Difference::Dir(changes)))
+        }
+        _ => Ok(Some(Difference::KindMismatch)),
+    }
+}
+
+/// How the file at `link` differs from the rendering of the template at `template`.
+fn compare_rendered(
+    template: &Path,
+    link: &Path,
+    vars: &Vars,
+) -> Result<Option<Difference>, DiffError> {
+    match (template.exists(), link.exists()) {
+        (false, false) => return Ok(None),
+        (false, true) => return Ok(Some(Difference::MissingSource)),
+        (true, false) => return Ok(Some(Difference::MissingLink)),
+        (true, true) if link.is_dir() => return Ok(Some(Difference::KindMismatch)),
+        (true, true) => {}
+    }
+    let rendered = render_file(template, vars)?;
+    Ok(compare_contents(
+        template,
+        rendered.into_bytes(),
+        link,
+        fs::read(link)?,
+    )?)
+}
+
+/// Differences between the project sources and what is at their link paths,
+/// for every dotfile or only `dotfile`.
+///
+/// Dotfiles linked to their source never differ and are left out, as are
+/// dotfiles not for this machine. Templates are compared by their rendering.
+pub fn project_diff(
+    ctx: &Context,
+    dotfile: Option<&SourcePath>,
+) -> Result<Vec<DotfileDiff>, DiffError> {
+    use DiffError as E;
+    let project = ctx.project();
+    let config_path = project.join(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        return Err(E::ProjectNotInitialized);
+    }
+    let config = DotConfig::from_file(&config_path)?;
+    if dotfile.is_some_and(|s| !config.dotfiles.contains_key(s)) {
+        return Err(E::DotfileNotRecorded);
+    }
+    let vars = Vars::load(ctx)?;
+    let machine = Machine::current(ctx);
+    let mut diffs = vec![];
+    for (source, entry) in config.dotfiles.iter() {
+        if dotfile.is_some_and(|s| s != source) || !machine.applies(entry) {
+            continue;
+        }
+        let abs_source = active_source(ctx, source).unwrap_or_else(|| project.join(source));
+        let abs_link = ctx.home().join(&entry.link);
+        let difference = match entry.mode {
+            Mode::Template => compare_rendered(&abs_source, &abs_link, &vars)?,
+            _ => compare(&abs_source, &abs_link)?,
+        };
+        if let Some(difference) = difference {
+            diffs.push(DotfileDiff {
+                source: source.clone(),
+                abs_source,
+                abs_link,
+                difference,
+            });
+        }
+    }
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        example::{example_complete_setup_from_structure, get_example_structure},
+        restore::{self, RestoreOptions},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+
+    #[rstest]
+    fn diff_copies(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("diff_copies")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        assert!(project_diff(&ctx, None).unwrap().is_empty());
+
+        let options = RestoreOptions {
+            copy: true,
+            ..Default::default()
+        };
+        restore::restore(&ctx, options).unwrap();
+        let bashrc = f.home.join(&f.bashrc.link);
+        let nvim = f.home.join(&f.nvim.link);
+        fs::write(&bashrc, "changed\n").unwrap();
+        fs::write(nvim.join("new.lua"), "").unwrap();
+        fs::write(nvim.join("binary"), [0xff, 0xfe]).unwrap();
+        fs::write(f.dotfiles.join(&f.nvim.source).join("binary"), [0xff]).unwrap();
+
+        let diffs = project_diff(&ctx, None).unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert!(matches!(&diffs[0].difference, Difference::Text(d) if d.contains("+changed\n")));
+        assert_eq!(
+            diffs[1].difference,
+            Difference::Dir(vec![
+                Change::ChangedBinary(PathBuf::from("binary")),
+                Change::Added(PathBuf::from("new.lua")),
+            ])
+        );
+        assert_eq!(
+            compare(
+                &f.dotfiles.join(&f.nvim.source).join("binary"),
+                &nvim.join("binary")
+            )
+            .unwrap(),
+            Some(Difference::Binary)
+        );
+        assert!(diffs[1].to_string().contains("  changed binary (binary)\n"));
+    }
+}
@@ -1,16 +1,13 @@
 use std::fs::{self, create_dir_all};
-use std::{
-    env::{self, current_dir},
-    io,
-    path::Path,
-};
+use std::{io, path::Path};
 
 use crate::setup;
 use crate::{
-    add, init,
+    add,
+    context::Context,
+    init,
     types::{LinkPath, ProjectPath, SourcePath},
     utils::{normalize_path, AbsPath},
-    HOME_ENV,
 };
 
 #[derive(Debug)]
@@ -27,6 +24,17 @@ pub struct ExampleStructure {
     pub bashrc: ExampleDotfile,
 }
 
+impl ExampleStructure {
+    pub fn context(&self) -> Context {
+        Context::builder()
+            .home(self.home.to_path_buf())
+            .cwd(self.home.to_path_buf())
+            .project(self.dotfiles.to_path_buf())
+            .build()
+            .expect("example paths to be absolute")
+    }
+}
+
 pub fn get_example_structure<P: AsRef<Path>, H: AsRef<Path>, W: AsRef<Path>>(
     base_dir: P,
     home: H,
@@ -55,74 +63,64 @@ pub fn get_example_structure<P: AsRef<Path>, H: AsRef<Path>, W: AsRef<Path>>(
     }
 }
 
-pub fn example_new_user<P: AsRef<Path>>(base_dir: P) -> io::Result<()> {
-    let home = AbsPath::new(env::var(HOME_ENV).expect("Home var not set.")).expect("");
-    let cwd = current_dir().expect("There is a current dir.");
-    let f = get_example_structure(base_dir, &home, &cwd);
+pub fn example_new_user<P: AsRef<Path>>(ctx: &Context, base_dir: P) -> io::Result<()> {
+    let f = get_example_structure(base_dir, ctx.home(), ctx.cwd());
     example_new_user_from_structure(&f)
 }
 
 pub fn example_new_user_from_structure(f: &ExampleStructure) -> io::Result<()> {
     create_dir_all(&f.dotfiles)?;
-    create_dir_all(&f.home.join(&f.nvim.link))?;
-    fs::write(&f.home.join(&f.bashrc.link), "basrc content")?;
+    create_dir_all(f.home.join(&f.nvim.link))?;
+    fs::write(f.home.join(&f.bashrc.link), "basrc content")?;
     fs::write(
-        &f.home.join(&f.nvim.link).join("init.lua"),
+        f.home.join(&f.nvim.link).join("init.lua"),
         "init dot lua content",
     )?;
     Ok(())
 }
 
-pub fn example_new_machine<P: AsRef<Path>>(base_dir: P) -> io::Result<()> {
-    let base_dir = base_dir.as_ref();
-    let home = AbsPath::new(env::var(HOME_ENV).expect("Home var not set.")).expect("");
-    let cwd = current_dir().expect("There is a current dir.");
-    let f = get_example_structure(base_dir, &home, &cwd);
+pub fn example_new_machine<P: AsRef<Path>>(ctx: &Context, base_dir: P) -> io::Result<()> {
+    let f = get_example_structure(base_dir, ctx.home(), ctx.cwd());
     example_new_machine_from_structure(&f)?;
     Ok(())
 }
 
 pub fn example_new_machine_from_structure(f: &ExampleStructure) -> io::Result<()> {
     example_new_user_from_structure(f)?;
-    init::init_project(&f.dotfiles).expect("A");
-    add::add(&f.home, &f.bashrc.link, &f.dotfiles, &f.bashrc.source).expect("B");
-    add::add(&f.home, &f.nvim.link, &f.dotfiles, &f.nvim.source).expect("C");
-    fs::remove_file(&f.home.join(&f.bashrc.link))?;
-    fs::remove_dir_all(&f.home.join(&f.nvim.link).parent().unwrap())?;
+    let ctx = f.context();
+    init::init_project(&ctx).expect("A");
+    add::add(&ctx, &f.bashrc.link, &f.bashrc.source).expect("B");
+    add::add(&ctx, &f.nvim.link, &f.nvim.source).expect("C");
+    fs::remove_file(f.home.join(&f.bashrc.link))?;
+    fs::remove_dir_all(f.home.join(&f.nvim.link).parent().unwrap())?;
     Ok(())
 }
 
 pub fn example_complete_setup_from_structure(f: &ExampleStructure) -> io::Result<()> {
     example_new_machine_from_structure(f)?;
-    setup::setup_project(&f.dotfiles, &f.home).expect("setup to work");
+    setup::setup_project(&f.context(), Default::default()).expect("setup to work");
     Ok(())
 }
 
-pub fn example_complete_setup<P: AsRef<Path>>(base_dir: P) -> io::Result<()> {
-    let base_dir = base_dir.as_ref();
-    let home = AbsPath::new(env::var(HOME_ENV).expect("Home var not set.")).expect("");
-    let cwd = current_dir().expect("There is a current dir.");
-    let f = get_example_structure(base_dir, &home, &cwd);
+pub fn example_complete_setup<P: AsRef<Path>>(ctx: &Context, base_dir: P) -> io::Result<()> {
+    let f = get_example_structure(base_dir, ctx.home(), ctx.cwd());
     example_complete_setup_from_structure(&f)?;
     Ok(())
 }
 
 pub fn example_new_dotfile_from_structure(f: &ExampleStructure) -> io::Result<()> {
     example_new_user_from_structure(f)?;
-    init::init_project(&f.dotfiles).expect("A");
-    fs::rename(&f.home.join(&f.nvim.link), &f.dotfiles.join(&f.nvim.source))?;
+    init::init_project(&f.context()).expect("A");
+    fs::rename(f.home.join(&f.nvim.link), f.dotfiles.join(&f.nvim.source))?;
     fs::rename(
-        &f.home.join(&f.bashrc.link),
-        &f.dotfiles.join(&f.bashrc.source),
+        f.home.join(&f.bashrc.link),
+        f.dotfiles.join(&f.bashrc.source),
     )?;
     Ok(())
 }
 
-pub fn example_new_dotfile<P: AsRef<Path>>(base_dir: P) -> io::Result<()> {
-    let base_dir = base_dir.as_ref();
-    let home = AbsPath::new(env::var(HOME_ENV).expect("Home var not set.")).expect("");
-    let cwd = current_dir().expect("There is a current dir.");
-    let f = get_example_structure(base_dir, &home, &cwd);
+pub fn example_new_dotfile<P: AsRef<Path>>(ctx: &Context, base_dir: P) -> io::Result<()> {
+    let f = get_example_structure(base_dir, ctx.home(), ctx.cwd());
     example_new_dotfile_from_structure(&f)?;
     Ok(())
 }
@@ -0,0 +1,181 @@
+use std::{fs, io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::{
+    alternate::active_source,
+    context::Context,
+    diff,
+    plan::{ApplyError, Operation, Plan},
+    status::{self, LinkState, SourceState, StatusError},
+    types::SourcePath,
+};
+
+#[derive(Error, Debug)]
+pub enum HealError {
+    #[error("could not check project: {0}")]
+    Status(#[from] StatusError),
+    #[error("dotfile not recorded in config")]
+    DotfileNotRecorded,
+    #[error("could not compare files: {0}")]
+    Compare(#[from] io::Error),
+    #[error("could not heal dotfile: {0}")]
+    Apply(#[from] ApplyError),
+}
+
+/// A dotfile whose link was replaced by a regular file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replaced {
+    pub source: SourcePath,
+    /// The regular file now at the link path.
+    pub abs_link: PathBuf,
+    pub abs_source: PathBuf,
+    /// Whether the file in the home directory was modified after the project source.
+    pub home_is_newer: bool,
+}
+
+impl Replaced {
+    /// Unified diff from the project source to the file in the home directory.
+    pub fn diff(&self) -> io::Result<String> {
+        diff::file_diff(&self.abs_source, &self.abs_link)
+    }
+}
+
+/// Which copy of a replaced dotfile to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// Move the file in the home directory into the project.
+    Home,
+    /// Discard the file in the home directory.
+    Project,
+}
+
+/// How to pick the copy to keep without asking.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HealPolicy {
+    /// Keep whichever copy was modified last.
+    #[default]
+    Newer,
+    Home,
+    Project,
+}
+
+impl HealPolicy {
+    pub fn choose(&self, replaced: &Replaced) -> Keep {
+        match self {
+            HealPolicy::Newer if replaced.home_is_newer => Keep::Home,
+            HealPolicy::Newer => Keep::Project,
+            HealPolicy::Home => Keep::Home,
+            HealPolicy::Project => Keep::Project,
+        }
+    }
+}
+
+/// Find the dotfiles of the project, or only `dotfile`, whose link was replaced by a regular file.
+pub fn find_replaced(
+    ctx: &Context,
+    dotfile: Option<&SourcePath>,
+) -> Result<Vec<Replaced>, HealError> {
+    let status = status::project_status(ctx)?;
+    if dotfile.is_some_and(|s| !status.dotfiles.iter().any(|d| &d.source == s)) {
+        return Err(HealError::DotfileNotRecorded);
+    }
+    let mut replaced = vec![];
+    for d in status.dotfiles {
+        if dotfile.is_some_and(|s| s != &d.source)
+            || d.source_state != SourceState::Present
+            || d.link_state != LinkState::OccupiedByFile
+        {
+            continue;
+        }
+        let Some(abs_source) = active_source(ctx, &d.source).filter(|s| s.is_file()) else {
+            continue;
+        };
+        let abs_link = ctx.home().join(&d.link);
+        let home_is_newer =
+            fs::metadata(&abs_link)?.modified()? > fs::metadata(&abs_source)?.modified()?;
+        replaced.push(Replaced {
+            source: d.source,
+            abs_link,
+            abs_source,
+            home_is_newer,
+        });
+    }
+    Ok(replaced)
+}
+
+/// Plan keeping the chosen copy of each replaced dotfile and linking it again.
+pub fn plan_heal(ctx: &Context, choices: &[(Replaced, Keep)]) -> Plan {
+    let mut plan = Plan::journaled(ctx.project());
+    for (replaced, keep) in choices {
+        plan.begin_entry(&replaced.source);
+        match keep {
+            Keep::Home => {
+                plan.remove(replaced.abs_source.clone());
+                plan.push(Operation::Move {
+                    from: replaced.abs_link.clone(),
+                    to: replaced.abs_source.clone(),
+                });
+            }
+            Keep::Project => plan.remove(replaced.abs_link.clone()),
+        }
+        plan.push(Operation::Symlink {
+            link: replaced.abs_link.clone(),
+            target: replaced.abs_source.clone(),
+        });
+    }
+    plan
+}
+
+/// Heal every replaced dotfile of the project, picking the copy to keep by `policy`.
+pub fn heal(ctx: &Context, policy: HealPolicy) -> Result<Vec<Replaced>, HealError> {
+    let replaced = find_replaced(ctx, None)?;
+    let choices: Vec<_> = replaced
+        .iter()
+        .map(|r| (r.clone(), policy.choose(r)))
+        .collect();
+    plan_heal(ctx, &choices).apply()?;
+    Ok(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        example::{example_complete_setup_from_structure, get_example_structure},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+    use std::time::{Duration, SystemTime};
+
+    #[rstest]
+    fn heal_replaced_link(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("heal_replaced_link")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let link = f.home.join(&f.bashrc.link);
+        let source = f.dotfiles.join(&f.bashrc.source);
+        fs::write(&source, "alias ll='ls -l'\n").unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .and_then(|file| file.set_modified(an_hour_ago))
+            .unwrap();
+        fs::remove_file(&link).unwrap();
+        fs::write(&link, "alias ll='ls -la'\n").unwrap();
+
+        let replaced = find_replaced(&ctx, None).unwrap();
+        assert_eq!(replaced.len(), 1);
+        assert!(replaced[0].home_is_newer);
+        let diff = replaced[0].diff().unwrap();
+        assert!(diff.contains("-alias ll='ls -l'\n+alias ll='ls -la'\n"));
+
+        heal(&ctx, HealPolicy::Newer).unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(fs::read_to_string(&source).unwrap(), "alias ll='ls -la'\n");
+        assert!(find_replaced(&ctx, None).unwrap().is_empty());
+    }
+}
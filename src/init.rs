@@ -1,4 +1,4 @@
-use crate::{config, types::ProjectPath, CONFIG_FILE_NAME};
+use crate::{config, context::Context, CONFIG_FILE_NAME};
 use std::{fs::create_dir_all, io};
 use thiserror::Error;
 
@@ -12,8 +12,9 @@ pub enum InitError {
     WriteError(#[from] config::WriteError),
 }
 
-pub fn init_project(project: &ProjectPath) -> Result<(), InitError> {
+pub fn init_project(ctx: &Context) -> Result<(), InitError> {
     use InitError as E;
+    let project = ctx.project();
     let config_path = project.join(CONFIG_FILE_NAME);
     if config_path.exists() {
         return Err(E::ConfigFileExists);
@@ -21,40 +22,51 @@ pub fn init_project(project: &ProjectPath) -> Result<(), InitError> {
     create_dir_all(project)?;
     let config = config::DotConfig::new();
     config.write(&config_path)?;
-    return Ok(());
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tests::root_dir;
+    use crate::utils::AbsPath;
     use rstest::rstest;
-    use std::{fs::create_dir, path::PathBuf};
+    use std::fs::create_dir;
 
     #[rstest]
-    fn basic_init(root_dir: &PathBuf) {
-        let test_dir = ProjectPath::new(root_dir.join("basic_init")).unwrap();
-        create_dir(&test_dir).expect("Could not create `test_dir`.");
+    fn basic_init(root_dir: &AbsPath) {
+        let ctx = Context::builder()
+            .home(root_dir.to_path_buf())
+            .project(root_dir.join("basic_init"))
+            .build()
+            .unwrap();
+        let test_dir = ctx.project();
+        create_dir(test_dir).expect("Could not create `test_dir`.");
         let config = test_dir.join(CONFIG_FILE_NAME);
-        let _ = create_dir(&test_dir);
-        assert!(init_project(&test_dir).is_ok());
+        let _ = create_dir(test_dir);
+        assert!(init_project(&ctx).is_ok());
         match config::DotConfig::from_file(config) {
             Ok(c) => assert_eq!(c, config::DotConfig::new()),
-            Err(e) => assert!(false, "{}", e),
+            Err(e) => panic!("{}", e),
         }
     }
 
     #[rstest]
-    fn basic_deep_init(root_dir: &PathBuf) {
-        let test_dir = ProjectPath::new(root_dir.join("basic_deep_init")).unwrap();
+    fn basic_deep_init(root_dir: &AbsPath) {
+        let ctx = Context::builder()
+            .home(root_dir.to_path_buf())
+            .project(root_dir.join("basic_deep_init"))
+            .build()
+            .unwrap();
+        let test_dir = ctx.project();
         assert!(!test_dir.exists());
         let config = test_dir.join(CONFIG_FILE_NAME);
-        let _ = create_dir(&test_dir);
-        assert!(init_project(&test_dir).is_ok());
+        let _ = create_dir(test_dir);
+        assert!(init_project(&ctx).is_ok());
         assert!(test_dir.exists());
         match config::DotConfig::from_file(config) {
             Ok(c) => assert_eq!(c, config::DotConfig::new()),
-            Err(e) => assert!(false, "{}", e),
+            Err(e) => panic!("{}", e),
         }
     }
 }
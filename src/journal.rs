@@ -0,0 +1,199 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    context::Context,
+    plan::{Operation, Plan},
+    types::ProjectPath,
+};
+
+/// Journal of the operation in progress, relative to the project root.
+pub const JOURNAL_FILE_NAME: &str = ".dotman/journal";
+
+/// On-disk record of a plan being applied.
+///
+/// Written before every step, so after a crash the steps in `0..started`
+/// may have been applied and every later step certainly was not.
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalFile {
+    started: usize,
+    operations: Vec<Operation>,
+}
+
+pub(crate) struct Journal {
+    path: PathBuf,
+}
+
+pub fn journal_path(project: &ProjectPath) -> PathBuf {
+    project.join(JOURNAL_FILE_NAME)
+}
+
+impl Journal {
+    pub(crate) fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub(crate) fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record that `operations[..started]` may have been applied.
+    pub(crate) fn record(&self, operations: &[Operation], started: usize) -> io::Result<()> {
+        let journal = JournalFile {
+            started,
+            operations: operations.to_vec(),
+        };
+        let content = toml::to_string(&journal).map_err(io::Error::other)?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn read(&self) -> Result<JournalFile, RecoverError> {
+        let content = fs::read_to_string(&self.path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Remove the journal, and its directory if nothing else is in it.
+    pub(crate) fn clear(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)?;
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RecoverError {
+    #[error("could not read journal: {0}")]
+    IO(#[from] io::Error),
+    #[error("could not parse journal: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// How to deal with an interrupted operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoverMode {
+    /// Undo every step that may have been applied.
+    Revert,
+    /// Apply the steps that were not applied yet.
+    Finish,
+}
+
+/// Finish or revert the operation recorded in the project journal.
+///
+/// Returns the interrupted plan, or `None` if there was nothing to recover.
+pub fn recover(ctx: &Context, mode: RecoverMode) -> Result<Option<Plan>, RecoverError> {
+    let journal = Journal::new(journal_path(ctx.project()));
+    if !journal.exists() {
+        return Ok(None);
+    }
+    let JournalFile {
+        started,
+        operations,
+    } = journal.read()?;
+    let started = started.min(operations.len());
+    match mode {
+        RecoverMode::Revert => {
+            for op in operations[..started].iter().rev() {
+                op.rollback()?;
+            }
+        }
+        RecoverMode::Finish => {
+            for (i, op) in operations
+                .iter()
+                .enumerate()
+                .skip(started.saturating_sub(1))
+            {
+                if i + 1 == started && op.is_applied() {
+                    continue;
+                }
+                journal.record(&operations, i + 1)?;
+                op.apply()?;
+            }
+            for op in operations.iter() {
+                op.finalize()?;
+            }
+        }
+    }
+    journal.clear()?;
+    let mut plan = Plan::new();
+    for op in operations {
+        plan.push(op);
+    }
+    Ok(Some(plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        add,
+        config::DotConfig,
+        example::{example_new_user_from_structure, get_example_structure, ExampleStructure},
+        init,
+        plan::ApplyError,
+        tests::root_dir,
+        utils::AbsPath,
+        CONFIG_FILE_NAME,
+    };
+    use rstest::rstest;
+
+    /// Simulate a crash right after the first `steps` operations of adding bashrc.
+    fn interrupted_add(root_dir: &AbsPath, name: &str, steps: usize) -> ExampleStructure {
+        let test_dir = AbsPath::new(root_dir.join(name)).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_user_from_structure(&f).unwrap();
+        let ctx = f.context();
+        init::init_project(&ctx).unwrap();
+        let plan = add::plan_add(&ctx, &f.bashrc.link, &f.bashrc.source).unwrap();
+        let journal = Journal::new(journal_path(ctx.project()));
+        for (i, op) in plan.operations()[..steps].iter().enumerate() {
+            journal.record(plan.operations(), i + 1).unwrap();
+            op.apply().unwrap();
+        }
+        f
+    }
+
+    #[rstest]
+    fn recover_revert(root_dir: &AbsPath) {
+        let f = interrupted_add(root_dir, "recover_revert", 1);
+        let ctx = f.context();
+        let link = ctx.home().join(&f.bashrc.link);
+        assert!(!link.exists());
+        assert!(matches!(
+            add::add(&ctx, &f.nvim.link, &f.nvim.source),
+            Err(add::AddError::Apply(ApplyError::Interrupted(_)))
+        ));
+        let plan = recover(&ctx, RecoverMode::Revert).unwrap();
+        assert!(plan.is_some());
+        assert!(link.exists() && !link.is_symlink());
+        assert!(!ctx.project().join("bashrc").exists());
+        assert!(!journal_path(ctx.project()).exists());
+        assert!(recover(&ctx, RecoverMode::Revert).unwrap().is_none());
+    }
+
+    #[rstest]
+    fn recover_finish(root_dir: &AbsPath) {
+        let f = interrupted_add(root_dir, "recover_finish", 1);
+        let ctx = f.context();
+        recover(&ctx, RecoverMode::Finish).unwrap();
+        assert!(ctx.home().join(&f.bashrc.link).is_symlink());
+        let config = DotConfig::from_file(ctx.project().join(CONFIG_FILE_NAME)).unwrap();
+        assert!(config.dotfiles.contains_key(&f.bashrc.source));
+        assert!(!journal_path(ctx.project()).exists());
+    }
+}
@@ -0,0 +1,78 @@
+//! Manage dotfiles by moving them into a project directory and linking them back.
+//!
+//! The binary is a thin wrapper around the operations exported here, so other
+//! tools can embed dotman directly instead of shelling out to it.
+
+pub mod add;
+pub mod alternate;
+pub mod config;
+pub mod context;
+pub mod diff;
+/// Demo file structures for `dotman example` and the tests, not part of the API.
+#[doc(hidden)]
+pub mod example;
+pub mod heal;
+pub mod init;
+pub mod journal;
+pub mod list;
+pub mod machine;
+pub mod mv;
+pub mod plan;
+pub mod remove;
+pub mod restore;
+pub mod setup;
+pub mod status;
+pub mod sync;
+pub mod template;
+pub mod types;
+pub mod update;
+pub mod utils;
+
+pub use config::DotConfig;
+pub use context::Context;
+pub use types::{LinkPath, ProjectPath, SourcePath};
+pub use utils::AbsPath;
+
+pub const HOME_ENV: &str = if cfg!(test) { "TEST_HOME" } else { "HOME" };
+pub const DOTMAN_HOME_ENV: &str = "DOTMAN_HOME";
+pub const DOTMAN_PROFILE_ENV: &str = "DOTMAN_PROFILE";
+pub const CONFIG_FILE_NAME: &str = ".dotman.toml";
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        env::{self, current_dir, temp_dir},
+        fs::create_dir,
+    };
+
+    use super::*;
+    use rstest::fixture;
+
+    const TEST_BASE_DIR_ENV: &str = "TEST_BASE_DIR";
+
+    #[fixture]
+    #[once]
+    pub fn root_dir() -> AbsPath {
+        let test_base_dir = if let Ok(test_base_dir) = env::var(TEST_BASE_DIR_ENV) {
+            let home = env::var("HOME").expect("Home env var set.");
+            let cwd = current_dir().expect("There is a current dir.");
+            utils::normalize_path(test_base_dir, home, cwd)
+        } else {
+            let time_format = "%Y-%m-%d_%H-%M-%S";
+            let current_time = chrono::offset::Local::now();
+            let current_time_str = format!("{}", current_time.format(time_format));
+            let base_name = "dotman-rs-test_";
+            temp_dir().join(base_name.to_owned() + &current_time_str)
+        };
+        if !test_base_dir.exists() {
+            create_dir(&test_base_dir).expect("Could not create test root directory.");
+        }
+        AbsPath::new(test_base_dir).expect("Test root directory to be absolute.")
+    }
+
+    #[test]
+    fn test_env_var_processing() {
+        assert_eq!(HOME_ENV, "TEST_HOME");
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::{
+    config::{self, DotConfig, DotEntry, Mode},
+    context::Context,
+    types::SourcePath,
+    CONFIG_FILE_NAME,
+};
+
+#[derive(Error, Debug)]
+pub enum ListError {
+    #[error("project not initialized")]
+    ProjectNotInitialized,
+    #[error("could not read dotman config: {0}")]
+    ReadConfigError(#[from] config::ReadError),
+}
+
+/// A recorded dotfile, as shown by `dotman list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedDotfile {
+    pub source: SourcePath,
+    pub entry: DotEntry,
+}
+
+impl fmt::Display for ListedDotfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> ~/{}",
+            self.source.display(),
+            self.entry.link.display()
+        )?;
+        if self.entry.mode != Mode::default() {
+            write!(f, " ({})", self.entry.mode)?;
+        }
+        if !self.entry.tags.is_empty() {
+            write!(f, " [{}]", self.entry.tags.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Every recorded dotfile matching the tags of `ctx`.
+pub fn list_dotfiles(ctx: &Context) -> Result<Vec<ListedDotfile>, ListError> {
+    let config_path = ctx.project().join(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        return Err(ListError::ProjectNotInitialized);
+    }
+    let config = DotConfig::from_file(&config_path)?;
+    Ok(config
+        .dotfiles
+        .into_iter()
+        .filter(|(_, entry)| ctx.tags().matches(entry))
+        .map(|(source, entry)| ListedDotfile { source, entry })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::TagFilter,
+        example::{example_new_machine_from_structure, get_example_structure},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+
+    #[rstest]
+    fn list_by_tag(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("list_by_tag")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        for (entry, tags) in config.dotfiles.values_mut().zip([["shell"], ["editor"]]) {
+            entry.tags = tags.iter().map(|t| t.to_string()).collect();
+        }
+        config.write(&config_path).unwrap();
+
+        let listed = list_dotfiles(&f.context()).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].to_string(), "bashrc -> ~/bashrc [shell]");
+
+        let with_tags = |tags: TagFilter| {
+            let ctx = Context::builder()
+                .home(f.home.to_path_buf())
+                .cwd(f.home.to_path_buf())
+                .project(f.dotfiles.to_path_buf())
+                .tags(tags)
+                .build()
+                .unwrap();
+            let listed = list_dotfiles(&ctx).unwrap();
+            listed.into_iter().map(|l| l.source).collect::<Vec<_>>()
+        };
+        let editor = TagFilter {
+            include: vec!["editor".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(with_tags(editor), vec![f.nvim.source.clone()]);
+        let no_editor = TagFilter {
+            exclude: vec!["editor".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(with_tags(no_editor), vec![f.bashrc.source.clone()]);
+    }
+}
@@ -0,0 +1,84 @@
+use std::env;
+
+use crate::{
+    config::{Condition, DotEntry},
+    context::Context,
+    template,
+};
+
+/// The machine dotman runs on, as far as entry conditions are concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Machine {
+    pub hostname: String,
+    pub os: String,
+    pub profiles: Vec<String>,
+}
+
+impl Machine {
+    /// This machine, with the profiles activated in `ctx`.
+    pub fn current(ctx: &Context) -> Self {
+        Self {
+            hostname: template::hostname(),
+            os: env::consts::OS.to_string(),
+            profiles: ctx.profiles().to_vec(),
+        }
+    }
+
+    /// Whether every condition of `when` holds on this machine.
+    pub fn satisfies(&self, when: &Condition) -> bool {
+        (when.profiles.is_empty() || when.profiles.iter().any(|p| self.profiles.contains(p)))
+            && one_of(&when.hostnames, &self.hostname)
+            && one_of(&when.os, &self.os)
+            && when.env.iter().all(|spec| env_holds(spec))
+    }
+
+    /// Whether `entry` is to be set up on this machine.
+    pub fn applies(&self, entry: &DotEntry) -> bool {
+        self.satisfies(&entry.when)
+    }
+}
+
+/// Whether `actual` is among `values`, or `values` does not restrict anything.
+fn one_of(values: &[String], actual: &str) -> bool {
+    values.is_empty() || values.iter().any(|v| v == actual)
+}
+
+fn env_holds(spec: &str) -> bool {
+    match spec.split_once('=') {
+        Some((name, value)) => env::var(name).is_ok_and(|v| v == value),
+        None => env::var_os(spec).is_some_and(|v| !v.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_satisfies() {
+        let machine = Machine {
+            hostname: "laptop".to_string(),
+            os: "linux".to_string(),
+            profiles: vec!["work".to_string()],
+        };
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(machine.satisfies(&Condition::default()));
+        let when = Condition {
+            hostnames: strings(&["desktop", "laptop"]),
+            os: strings(&["linux"]),
+            ..Default::default()
+        };
+        assert!(machine.satisfies(&when));
+        let when = Condition {
+            profiles: strings(&["personal"]),
+            os: strings(&["linux"]),
+            ..Default::default()
+        };
+        assert!(!machine.satisfies(&when));
+        let when = Condition {
+            env: strings(&["DOTMAN_SURELY_UNSET_VARIABLE"]),
+            ..Default::default()
+        };
+        assert!(!machine.satisfies(&when));
+    }
+}
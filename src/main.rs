@@ -1,37 +1,41 @@
-use std::env::current_dir;
 use std::error::Error;
+use std::io::{self, Write};
 use std::path::Path;
-use std::{env, io};
+use std::process::ExitCode;
 
 use clap::Parser;
-use types::{LinkPath, ProjectPath, SourcePath};
-use utils::{normalize_path, AbsPath};
+use dotman::config::TagFilter;
+use dotman::context::ContextBuilder;
+use dotman::heal::{self, HealPolicy, Keep};
+use dotman::journal::{self, RecoverMode};
+use dotman::plan::{ApplyError, Plan};
+use dotman::remove::{self, RemoveMode};
+use dotman::restore::RestoreOptions;
+use dotman::setup::{OnConflict, SetupOptions};
+use dotman::status::StatusOutcome;
+use dotman::sync::{self, SyncDirection};
+use dotman::{
+    add, diff, example, init, list, mv, restore, setup, status, update, utils, AbsPath, Context,
+    LinkPath, SourcePath,
+};
 
-mod add;
 mod cli;
-mod config;
-mod example;
-mod init;
-mod restore;
-mod setup;
-mod status;
-mod types;
-mod update;
-mod utils;
 
-const HOME_ENV: &str = if cfg!(test) { "TEST_HOME" } else { "HOME" };
-const CONFIG_FILE_NAME: &str = ".dotman.toml";
-
-fn run_command(command: cli::Commands) -> Result<(), Box<dyn Error>> {
-    let home = env::var(HOME_ENV).expect("Home var not set.");
-    let cwd = current_dir().expect("There is a current dir.");
+fn run_command(
+    builder: ContextBuilder,
+    command: cli::Commands,
+) -> Result<ExitCode, Box<dyn Error>> {
     match command {
         cli::Commands::Init(cmd_args) => {
-            let project = ProjectPath::new(normalize_path(cmd_args.project, &home, &cwd))?;
-            init::init_project(&project)?;
+            let ctx = builder.project(cmd_args.project).build()?;
+            init::init_project(&ctx)?;
+        }
+        cli::Commands::Example(sa) => {
+            let ctx = builder.build()?;
+            setup_project(&ctx, sa.base_dir, sa.example)?
         }
-        cli::Commands::Example(sa) => setup_project(sa.base_dir, sa.example)?,
         cli::Commands::Add(sa) => {
+            let ctx = builder.project(sa.project).build()?;
             let target = match sa.target {
                 Some(t) => t,
                 None => sa
@@ -40,124 +44,290 @@ fn run_command(command: cli::Commands) -> Result<(), Box<dyn Error>> {
                     .expect("source to not be an empty path")
                     .into(),
             };
-            let home = AbsPath::new(home)?;
-            let link = LinkPath::new(normalize_path(sa.source, &home, &cwd).strip_prefix(&home)?)?;
-            let project = ProjectPath::new(normalize_path(sa.project, &home, &cwd))?;
+            let link = LinkPath::new(ctx.normalize(sa.source).strip_prefix(ctx.home())?)?;
             let target = SourcePath::new(target)?;
-            add::add(&home, &link, &project, &target)?;
+            run_plan(add::plan_add(&ctx, &link, &target)?, sa.dry_run)?;
         }
         cli::Commands::Setup(args) => {
-            let home = AbsPath::new(home)?;
-            let project = ProjectPath::new(normalize_path(args.project, &home, &cwd))?;
-            match args.dotfile {
-                None => setup::setup_project(&project, &home)?,
+            let ctx = builder
+                .project(args.project)
+                .tags(tag_filter(args.tags))
+                .build()?;
+            let on_conflict = match args.on_conflict {
+                cli::OnConflict::Fail => OnConflict::Fail,
+                cli::OnConflict::Skip => OnConflict::Skip,
+                cli::OnConflict::Backup => OnConflict::Backup,
+                cli::OnConflict::Overwrite => OnConflict::Overwrite,
+                cli::OnConflict::Adopt => OnConflict::Adopt,
+            };
+            let options = SetupOptions { on_conflict };
+            let plan = match args.dotfile {
+                None => setup::plan_setup_project(&ctx, options)?,
                 Some(d) => {
                     let dotfile = SourcePath::new(d)?;
-                    setup::setup_dotfile(&project, &dotfile, &home)?;
+                    setup::plan_setup_dotfile(&ctx, &dotfile, options)?
                 }
-            }
+            };
+            run_plan(plan, args.dry_run)?;
         }
         cli::Commands::Status(args) => {
-            let home = AbsPath::new(home)?;
+            let ctx = builder
+                .project(args.project)
+                .tags(tag_filter(args.tags))
+                .build()?;
             if args.recursive {
-                let base_dir = AbsPath::new(normalize_path(args.project, &home, &cwd))?;
-                let projects = utils::find_dotman_projects(&base_dir);
-                for project in projects {
-                    status::project_summary(&project, &home)?;
-                    println!("");
+                let base_dir = AbsPath::new(ctx.project())?;
+                if !base_dir.is_dir() {
+                    return Err(format!("no directory found at {}", base_dir.display()).into());
+                }
+                let mut outcome = StatusOutcome::Clean;
+                let mut statuses = vec![];
+                for project in utils::find_dotman_projects(&base_dir) {
+                    match status::project_status(&ctx.with_project(project.clone())) {
+                        Ok(s) => {
+                            outcome = outcome.max(s.outcome());
+                            statuses.push(s);
+                        }
+                        Err(e) => {
+                            eprintln!("error: {}: {}", project.display(), e);
+                            outcome = StatusOutcome::Error;
+                        }
+                    }
                 }
+                print_statuses(&statuses, args.format)?;
+                return Ok(ExitCode::from(outcome.exit_code()));
             } else {
-                let project = ProjectPath::new(normalize_path(args.project, &home, &cwd))?;
-                status::project_summary(&project, &home)?;
+                let status = status::project_status(&ctx)?;
+                let outcome = status.outcome();
+                match args.format {
+                    cli::StatusFormat::Json => println!("{}", status.to_json()?),
+                    _ => print_statuses(&[status], args.format)?,
+                }
+                return Ok(ExitCode::from(outcome.exit_code()));
             }
         }
         cli::Commands::Update(args) => {
-            let home = AbsPath::new(home)?;
-            let link = LinkPath::new(normalize_path(args.link, &home, &cwd).strip_prefix(&home)?)?;
+            let ctx = builder.project(args.project).build()?;
+            let link = LinkPath::new(ctx.normalize(args.link).strip_prefix(ctx.home())?)?;
             let dotfile = SourcePath::new(args.dotfile)?;
-            let project = ProjectPath::new(normalize_path(args.project, &home, &cwd))?;
-            update::update(&home, &link, &dotfile, &project)?;
+            run_plan(update::plan_update(&ctx, &link, &dotfile)?, args.dry_run)?;
+        }
+        cli::Commands::List(args) => {
+            let ctx = builder
+                .project(args.project)
+                .tags(tag_filter(args.tags))
+                .build()?;
+            for dotfile in list::list_dotfiles(&ctx)? {
+                println!("{dotfile}");
+            }
         }
         cli::Commands::Restore(args) => {
-            let home = AbsPath::new(home)?;
-            let project = ProjectPath::new(normalize_path(args.project, &home, &cwd))?;
-            match args.dotfile {
-                None => restore::restore(&project, &home)?,
+            let ctx = builder
+                .project(args.project)
+                .tags(tag_filter(args.tags))
+                .build()?;
+            let options = RestoreOptions {
+                keep_record: args.keep_record,
+                copy: args.copy,
+            };
+            let plan = match args.dotfile {
+                None => restore::plan_restore(&ctx, options)?,
                 Some(d) => {
-                    restore::restore(&project, &home)?;
+                    let dotfile = SourcePath::new(d)?;
+                    restore::plan_restore_dotfile(&ctx, &dotfile, options)?
+                }
+            };
+            run_plan(plan, args.dry_run)?;
+        }
+        cli::Commands::Remove(args) => {
+            let ctx = builder.project(args.project).build()?;
+            let dotfile = SourcePath::new(args.dotfile)?;
+            let mode = match args.mode {
+                cli::RemoveMode::Record => RemoveMode::Record,
+                cli::RemoveMode::Link => RemoveMode::Link,
+                cli::RemoveMode::Source => RemoveMode::Source,
+            };
+            run_plan(remove::plan_remove(&ctx, &dotfile, mode)?, args.dry_run)?;
+        }
+        cli::Commands::Mv(args) => {
+            let ctx = builder.project(args.project).build()?;
+            let from = SourcePath::new(args.from)?;
+            let to = SourcePath::new(args.to)?;
+            run_plan(mv::plan_move(&ctx, &from, &to)?, args.dry_run)?;
+        }
+        cli::Commands::Heal(args) => {
+            let ctx = builder.project(args.project).build()?;
+            let dotfile = args.dotfile.map(SourcePath::new).transpose()?;
+            let replaced = heal::find_replaced(&ctx, dotfile.as_ref())?;
+            if replaced.is_empty() {
+                println!("nothing to heal");
+                return Ok(ExitCode::SUCCESS);
+            }
+            let mut choices = vec![];
+            for r in replaced {
+                let keep = match args.policy {
+                    cli::HealPolicy::Ask => match ask_keep(&r)? {
+                        Some(keep) => keep,
+                        None => continue,
+                    },
+                    cli::HealPolicy::Newer => HealPolicy::Newer.choose(&r),
+                    cli::HealPolicy::Home => Keep::Home,
+                    cli::HealPolicy::Project => Keep::Project,
+                };
+                choices.push((r, keep));
+            }
+            run_plan(heal::plan_heal(&ctx, &choices), args.dry_run)?;
+        }
+        cli::Commands::Diff(args) => {
+            let ctx = builder.project(args.project).build()?;
+            let dotfile = args.dotfile.map(SourcePath::new).transpose()?;
+            let diffs = diff::project_diff(&ctx, dotfile.as_ref())?;
+            for d in diffs.iter() {
+                print!("{d}");
+            }
+            if !diffs.is_empty() {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        cli::Commands::Sync(args) => {
+            let ctx = builder.project(args.project).build()?;
+            let dotfile = args.dotfile.map(SourcePath::new).transpose()?;
+            let direction = match args.direction {
+                cli::SyncDirection::ToHome => SyncDirection::ToHome,
+                cli::SyncDirection::ToProject => SyncDirection::ToProject,
+            };
+            run_plan(
+                sync::plan_sync(&ctx, dotfile.as_ref(), direction)?,
+                args.dry_run,
+            )?;
+        }
+        cli::Commands::Recover(args) => {
+            let ctx = builder.project(args.project).build()?;
+            let mode = if args.finish {
+                RecoverMode::Finish
+            } else {
+                RecoverMode::Revert
+            };
+            match journal::recover(&ctx, mode)? {
+                None => println!("no interrupted operation found"),
+                Some(plan) => {
+                    match mode {
+                        RecoverMode::Finish => println!("finished interrupted operation:"),
+                        RecoverMode::Revert => println!("reverted interrupted operation:"),
+                    }
+                    print!("{plan}");
                 }
             }
         }
     }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Show how a replaced dotfile differs and ask which copy to keep, `None` to skip it.
+fn ask_keep(replaced: &heal::Replaced) -> io::Result<Option<Keep>> {
+    print!("{}", replaced.diff()?);
+    let newer = if replaced.home_is_newer {
+        "home"
+    } else {
+        "project"
+    };
+    loop {
+        print!(
+            "{}: keep [h]ome or [p]roject copy, or [s]kip? ({newer} copy is newer) ",
+            replaced.source.display()
+        );
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match answer.trim() {
+            "h" | "home" => return Ok(Some(Keep::Home)),
+            "p" | "project" => return Ok(Some(Keep::Project)),
+            "s" | "skip" => return Ok(None),
+            _ => continue,
+        }
+    }
+}
+
+fn tag_filter(args: cli::TagArgs) -> TagFilter {
+    TagFilter {
+        include: args.include,
+        exclude: args.exclude,
+    }
+}
+
+/// Apply `plan`, or only print it when doing a dry run.
+fn run_plan(plan: Plan, dry_run: bool) -> Result<(), ApplyError> {
+    if dry_run {
+        print!("{plan}");
+        return Ok(());
+    }
+    plan.apply()?;
+    for (source, reason) in plan.skipped() {
+        println!("{}: {reason}", source.display());
+    }
+    Ok(())
+}
+
+fn print_statuses(
+    statuses: &[status::ProjectStatus],
+    format: cli::StatusFormat,
+) -> Result<(), serde_json::Error> {
+    match format {
+        cli::StatusFormat::Human => {
+            let blocks: Vec<String> = statuses.iter().map(|s| s.to_human()).collect();
+            print!("{}", blocks.join("\n"));
+        }
+        cli::StatusFormat::Porcelain => {
+            for status in statuses {
+                print!("{}", status.to_porcelain());
+            }
+        }
+        cli::StatusFormat::Json => println!("{}", serde_json::to_string_pretty(statuses)?),
+    }
     Ok(())
 }
 
-fn setup_project<P: AsRef<Path>>(base_dir: P, setup_type: cli::Examples) -> Result<(), io::Error> {
+fn setup_project<P: AsRef<Path>>(
+    ctx: &Context,
+    base_dir: P,
+    setup_type: cli::Examples,
+) -> Result<(), io::Error> {
     match setup_type {
-        cli::Examples::NewUser => example::example_new_user(base_dir)?,
-        cli::Examples::NewMachine => example::example_new_machine(base_dir)?,
-        cli::Examples::NewDotfile => example::example_new_dotfile(base_dir)?,
-        cli::Examples::CompleteSetup => example::example_complete_setup(base_dir)?,
+        cli::Examples::NewUser => example::example_new_user(ctx, base_dir)?,
+        cli::Examples::NewMachine => example::example_new_machine(ctx, base_dir)?,
+        cli::Examples::NewDotfile => example::example_new_dotfile(ctx, base_dir)?,
+        cli::Examples::CompleteSetup => example::example_complete_setup(ctx, base_dir)?,
     }
     Ok(())
 }
 
-fn main_cli() {
-    let args = cli::CLI::parse();
+fn main_cli() -> ExitCode {
+    let args = cli::Cli::parse();
+    let mut builder = Context::builder();
+    if let Some(home) = args.home {
+        builder = builder.home(home);
+    }
+    for profile in args.profile {
+        builder = builder.profile(profile);
+    }
 
     match args.command {
-        None => eprintln!("get help with `dotman --help`"),
-        Some(c) => match run_command(c) {
-            Ok(_) => (),
+        None => {
+            eprintln!("get help with `dotman --help`");
+            ExitCode::SUCCESS
+        }
+        Some(c) => match run_command(builder, c) {
+            Ok(code) => code,
             Err(e) => {
                 eprintln!("error: {}", e);
-                std::process::exit(1);
+                ExitCode::from(StatusOutcome::Error.exit_code())
             }
         },
-    };
-}
-
-fn main() {
-    main_cli();
-}
-
-#[cfg(test)]
-mod tests {
-
-    use chrono;
-    use std::{
-        env::{current_dir, temp_dir},
-        fs::create_dir,
-        path::PathBuf,
-    };
-
-    use super::*;
-    use rstest::fixture;
-
-    const TEST_BASE_DIR_ENV: &str = "TEST_BASE_DIR";
-
-    #[fixture]
-    #[once]
-    pub fn root_dir() -> PathBuf {
-        let test_base_dir = if let Ok(test_base_dir) = env::var(TEST_BASE_DIR_ENV) {
-            let home = env::var("HOME").expect("Home env var set.");
-            let cwd = current_dir().expect("There is a current dir.");
-            utils::normalize_path(test_base_dir, home, cwd)
-        } else {
-            let time_format = "%Y-%m-%d_%H-%M-%S";
-            let current_time = chrono::offset::Local::now();
-            let current_time_str = format!("{}", current_time.format(time_format));
-            let base_name = "dotman-rs-test_";
-            temp_dir().join(base_name.to_owned() + &current_time_str)
-        };
-        if !test_base_dir.exists() {
-            create_dir(&test_base_dir).expect("Could not create test root directory.");
-        }
-        return test_base_dir;
     }
+}
 
-    #[test]
-    fn test_env_var_processing() {
-        assert_eq!(HOME_ENV, "TEST_HOME");
-    }
+fn main() -> ExitCode {
+    main_cli()
 }
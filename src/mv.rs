@@ -0,0 +1,147 @@
+use thiserror::Error;
+
+use crate::{
+    alternate::{active_source, renamed, source_family},
+    config::{self, DotConfig},
+    context::Context,
+    plan::{ApplyError, Operation, Plan},
+    status::{link_state, LinkState},
+    types::SourcePath,
+    CONFIG_FILE_NAME,
+};
+
+#[derive(Error, Debug)]
+pub enum MoveError {
+    #[error("project not initialized")]
+    ProjectNotInitialized,
+    #[error("dotfile not recorded in config")]
+    DotfileNotRecorded,
+    #[error("dotfile not found")]
+    DotfileNotFound,
+    #[error("destination already recorded in config")]
+    DestinationRecorded,
+    #[error("destination already occupied")]
+    DestinationOccupied,
+    #[error("cannot move a dotfile into itself")]
+    DestinationInsideSource,
+    #[error("could not read dotman config: {0}")]
+    ReadConfigError(#[from] config::ReadError),
+    #[error("could not serialize dotman config: {0}")]
+    ConfigSerializationError(#[from] toml::ser::Error),
+    #[error("could not move dotfile: {0}")]
+    Apply(#[from] ApplyError),
+}
+
+/// Plan renaming the dotfile `from` to `to` within the project.
+///
+/// The config entry is renamed, the source and all of its alternates are
+/// moved and the link in the home directory, if it points at the old active
+/// source, is re-pointed.
+pub fn plan_move(ctx: &Context, from: &SourcePath, to: &SourcePath) -> Result<Plan, MoveError> {
+    use MoveError as E;
+    let project = ctx.project();
+    let home = ctx.home();
+    let config_path = project.join(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        return Err(E::ProjectNotInitialized);
+    }
+    let mut config = DotConfig::from_file(&config_path)?;
+    if config.dotfiles.contains_key(to) {
+        return Err(E::DestinationRecorded);
+    }
+    let entry = match config.dotfiles.remove(from) {
+        Some(v) => v,
+        None => return Err(E::DotfileNotRecorded),
+    };
+    let abs_from = project.join(from);
+    let abs_to = project.join(to);
+    if abs_to.starts_with(&abs_from) {
+        return Err(E::DestinationInsideSource);
+    }
+    let moves: Vec<_> = source_family(&abs_from)
+        .into_iter()
+        .filter_map(|path| {
+            let dest = renamed(&path, &abs_from, &abs_to)?;
+            Some((path, dest))
+        })
+        .collect();
+    if moves.is_empty() {
+        return Err(E::DotfileNotFound);
+    }
+    if moves
+        .iter()
+        .any(|(_, dest)| dest.exists() || dest.is_symlink())
+    {
+        return Err(E::DestinationOccupied);
+    }
+    let abs_link = home.join(&entry.link);
+    let relink = active_source(ctx, from)
+        .filter(|active| link_state(&abs_link, active) == LinkState::Linked)
+        .and_then(|active| renamed(&active, &abs_from, &abs_to));
+    let _ = config.dotfiles.insert(to.clone(), entry);
+    let mut plan = Plan::journaled(project);
+    plan.write_config(&config_path, &config)?;
+    plan.create_parents(&abs_to);
+    for (from, to) in moves {
+        plan.push(Operation::Move { from, to });
+    }
+    if let Some(target) = relink {
+        plan.remove(abs_link.clone());
+        plan.push(Operation::Symlink {
+            link: abs_link,
+            target,
+        });
+    }
+    Ok(plan)
+}
+
+pub fn move_dotfile(ctx: &Context, from: &SourcePath, to: &SourcePath) -> Result<(), MoveError> {
+    plan_move(ctx, from, to)?.apply()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Mode,
+        example::{example_complete_setup_from_structure, get_example_structure},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+    use std::fs;
+
+    #[rstest]
+    fn move_nested(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("move_nested")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let to = SourcePath::new("editors/nvim").unwrap();
+        move_dotfile(&ctx, &f.nvim.source, &to).unwrap();
+        let link = f.home.join(&f.nvim.link);
+        assert!(link.is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), f.dotfiles.join(&to));
+        assert!(!f.dotfiles.join(&f.nvim.source).exists());
+        let config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(
+            config.dotfiles.get(&to).map(|e| &e.link),
+            Some(&f.nvim.link)
+        );
+        assert!(!config.dotfiles.contains_key(&f.nvim.source));
+        assert!(matches!(
+            move_dotfile(&ctx, &to, &f.bashrc.source),
+            Err(MoveError::DestinationRecorded)
+        ));
+
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        config.dotfiles.get_mut(&f.bashrc.source).unwrap().mode = Mode::Copy;
+        config.write(&config_path).unwrap();
+        let to = SourcePath::new("shell/bashrc").unwrap();
+        move_dotfile(&ctx, &f.bashrc.source, &to).unwrap();
+        let link = f.home.join(&f.bashrc.link);
+        assert_eq!(fs::read_link(&link).unwrap(), f.dotfiles.join(&to));
+    }
+}
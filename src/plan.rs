@@ -0,0 +1,524 @@
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    os::unix::fs as unix_fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    config::DotConfig,
+    journal::{journal_path, Journal},
+    types::{ProjectPath, SourcePath},
+    utils::{copy_all, remove_all, same_inode},
+};
+
+/// A single filesystem change made by a dotman command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// Create a single directory, its parent must exist.
+    CreateDir { path: PathBuf },
+    /// Move a file or directory, `to` must not exist yet.
+    Move { from: PathBuf, to: PathBuf },
+    /// Create a symlink at `link` pointing at `target`.
+    Symlink { link: PathBuf, target: PathBuf },
+    /// Create a hard link at `link` to the file `target`.
+    Hardlink { link: PathBuf, target: PathBuf },
+    /// Write the rendered `template` to the new file `link`.
+    Render {
+        link: PathBuf,
+        template: PathBuf,
+        content: String,
+    },
+    /// Copy a file or directory tree, preserving permissions.
+    Copy { from: PathBuf, to: PathBuf },
+    /// Overwrite the project config, `previous` is restored on rollback.
+    WriteConfig {
+        path: PathBuf,
+        content: String,
+        previous: Option<String>,
+    },
+    /// Remove a file, symlink or directory.
+    ///
+    /// The path is first moved aside to `stash` and only deleted once the
+    /// whole plan succeeded, so it can be put back on rollback.
+    Remove { path: PathBuf, stash: PathBuf },
+}
+
+#[derive(Error, Debug)]
+pub enum ApplyError {
+    #[error("unexpected IO error, successfully rolled-back changes\n IO-Error: {0}")]
+    IO(#[from] io::Error),
+    #[error(
+        "unexpected IO error, could not roll-back changes\n io-error: {original_error}\n rollback-error: {rollback_error}"
+    )]
+    RollbackError {
+        original_error: io::Error,
+        rollback_error: io::Error,
+    },
+    #[error(
+        "unexpected IO error on dotfile {}, rolled back every dotfile{}\n IO-Error: {error}",
+        .failed.display(),
+        list_entries(.reverted)
+    )]
+    EntryFailed {
+        error: io::Error,
+        failed: SourcePath,
+        /// Dotfiles that had been applied before `failed`, and were undone.
+        reverted: Vec<SourcePath>,
+    },
+    #[error("changes applied, but could not clean up removed file {0}: {1}")]
+    CleanupError(PathBuf, io::Error),
+    #[error("found journal of an interrupted operation at {0}, run `dotman recover` first")]
+    Interrupted(PathBuf),
+    #[error("could not write journal: {0}")]
+    JournalError(io::Error),
+}
+
+fn list_entries(entries: &[SourcePath]) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+    let names: Vec<String> = entries.iter().map(|e| e.display().to_string()).collect();
+    format!(", reverted: {}", names.join(", "))
+}
+
+/// First of `path`, `path.1`, `path.2`, ... that does not exist and is not in `taken`.
+fn free_path(path: &Path, taken: &[&Path]) -> PathBuf {
+    (0..)
+        .map(|i| {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            if i > 0 {
+                name.push(format!(".{i}"));
+            }
+            path.with_file_name(name)
+        })
+        .find(|free| !(free.exists() || free.is_symlink() || taken.contains(&free.as_path())))
+        .expect("some numbered path to be free")
+}
+
+/// Error for an operation that would replace the existing `path`.
+fn already_exists(action: &str, path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("cannot {action}, {} exists", path.display()),
+    )
+}
+
+impl Operation {
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        match self {
+            Operation::CreateDir { path } => fs::create_dir(path),
+            Operation::Move { from, to } => {
+                if to.exists() || to.is_symlink() {
+                    return Err(already_exists("move", to));
+                }
+                fs::rename(from, to)
+            }
+            Operation::Symlink { link, target } => unix_fs::symlink(target, link),
+            Operation::Hardlink { link, target } => fs::hard_link(target, link),
+            Operation::Render { link, content, .. } => {
+                fs::File::create_new(link).and_then(|mut file| file.write_all(content.as_bytes()))
+            }
+            Operation::Copy { from, to } => copy_all(from, to).inspect_err(|_| {
+                let _ = remove_all(to);
+            }),
+            Operation::WriteConfig { path, content, .. } => fs::write(path, content),
+            Operation::Remove { path, stash } => {
+                if stash.exists() || stash.is_symlink() {
+                    return Err(already_exists("move removed file aside", stash));
+                }
+                fs::rename(path, stash)
+            }
+        }
+    }
+
+    pub(crate) fn rollback(&self) -> io::Result<()> {
+        match self {
+            Operation::CreateDir { path } => {
+                if path.is_dir() {
+                    fs::remove_dir(path)?;
+                }
+            }
+            Operation::Move { from, to } => {
+                if (to.exists() || to.is_symlink()) && !from.exists() {
+                    fs::rename(to, from)?;
+                }
+            }
+            Operation::Symlink { link, .. } => {
+                if link.is_symlink() {
+                    fs::remove_file(link)?;
+                }
+            }
+            Operation::Hardlink { link, target } => {
+                if same_inode(link, target) {
+                    fs::remove_file(link)?;
+                }
+            }
+            Operation::Copy { to, .. } | Operation::Render { link: to, .. } => {
+                if to.exists() || to.is_symlink() {
+                    remove_all(to)?;
+                }
+            }
+            Operation::WriteConfig { path, previous, .. } => match previous {
+                Some(previous) => fs::write(path, previous)?,
+                None => {
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            },
+            Operation::Remove { path, stash } => {
+                if (stash.exists() || stash.is_symlink()) && !path.exists() {
+                    fs::rename(stash, path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the effect of this operation is already visible on disk.
+    pub(crate) fn is_applied(&self) -> bool {
+        match self {
+            Operation::CreateDir { path } => path.is_dir(),
+            Operation::Move { from, to } => {
+                (to.exists() || to.is_symlink()) && !(from.exists() || from.is_symlink())
+            }
+            Operation::Symlink { link, target } => fs::read_link(link).is_ok_and(|t| t == *target),
+            Operation::Hardlink { link, target } => same_inode(link, target),
+            Operation::Copy { to, .. } => to.exists(),
+            Operation::Render { link, content, .. } => {
+                fs::read_to_string(link).is_ok_and(|c| c == *content)
+            }
+            Operation::WriteConfig { path, content, .. } => {
+                fs::read_to_string(path).is_ok_and(|c| c == *content)
+            }
+            Operation::Remove { path, .. } => !(path.exists() || path.is_symlink()),
+        }
+    }
+
+    pub(crate) fn finalize(&self) -> io::Result<()> {
+        if let Operation::Remove { stash, .. } = self
+            && (stash.exists() || stash.is_symlink())
+        {
+            remove_all(stash)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::CreateDir { path } => write!(f, "create dir {}", path.display()),
+            Operation::Move { from, to } => {
+                write!(f, "move {} -> {}", from.display(), to.display())
+            }
+            Operation::Symlink { link, target } => {
+                write!(f, "symlink {} -> {}", link.display(), target.display())
+            }
+            Operation::Hardlink { link, target } => {
+                write!(f, "hardlink {} -> {}", link.display(), target.display())
+            }
+            Operation::Copy { from, to } => {
+                write!(f, "copy {} -> {}", from.display(), to.display())
+            }
+            Operation::Render { link, template, .. } => {
+                write!(f, "render {} -> {}", template.display(), link.display())
+            }
+            Operation::WriteConfig { path, .. } => write!(f, "write config {}", path.display()),
+            Operation::Remove { path, .. } => write!(f, "remove {}", path.display()),
+        }
+    }
+}
+
+/// Ordered list of operations a command will perform.
+///
+/// Commands check their preconditions while building the plan, so a plan can
+/// be shown to the user before anything on disk is changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    operations: Vec<Operation>,
+    /// Index of the first operation of each dotfile, for plans spanning several.
+    entries: Vec<(usize, SourcePath)>,
+    /// Dotfiles left alone, with the reason why.
+    skipped: Vec<(SourcePath, String)>,
+    journal: Option<PathBuf>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plan whose progress is journaled in `project`, so an interrupted
+    /// apply can be finished or reverted with [`crate::journal::recover`].
+    pub fn journaled(project: &ProjectPath) -> Self {
+        Self {
+            operations: vec![],
+            entries: vec![],
+            skipped: vec![],
+            journal: Some(journal_path(project)),
+        }
+    }
+
+    /// Attribute the operations pushed from now on to the dotfile `source`.
+    pub fn begin_entry(&mut self, source: &SourcePath) {
+        self.entries.push((self.operations.len(), source.clone()));
+    }
+
+    /// The dotfile operation `index` belongs to, if any.
+    fn entry_of(&self, index: usize) -> Option<&SourcePath> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= index)
+            .map(|(_, source)| source)
+    }
+
+    /// Record that the dotfile `source` needs no changes.
+    pub fn skip<S: Into<String>>(&mut self, source: &SourcePath, reason: S) {
+        self.skipped.push((source.clone(), reason.into()));
+    }
+
+    pub fn skipped(&self) -> &[(SourcePath, String)] {
+        &self.skipped
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn push(&mut self, operation: Operation) {
+        self.operations.push(operation);
+    }
+
+    /// Add a [`Operation::CreateDir`] for every missing ancestor of `path`
+    /// not already created by this plan, or by the journal when applying it.
+    pub fn create_parents(&mut self, path: &Path) {
+        let journal_dir = self.journal.as_deref().and_then(Path::parent);
+        let mut missing: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists() && Some(*p) != journal_dir)
+            .map(Path::to_path_buf)
+            .collect();
+        missing.reverse();
+        for dir in missing {
+            let op = Operation::CreateDir { path: dir };
+            if !self.operations.contains(&op) {
+                self.operations.push(op);
+            }
+        }
+    }
+
+    /// Free path at or numbered after `path`, that no operation of this plan moves anything to.
+    pub fn free_path(&self, path: &Path) -> PathBuf {
+        let taken: Vec<&Path> = self
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Move { to, .. } => Some(to.as_path()),
+                Operation::Remove { stash, .. } => Some(stash.as_path()),
+                _ => None,
+            })
+            .collect();
+        free_path(path, &taken)
+    }
+
+    /// Add a [`Operation::Remove`] of `path`, stashing it at a path not used by anything else.
+    pub fn remove(&mut self, path: PathBuf) {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".dotman-removed");
+        let stash = self.free_path(&path.with_file_name(name));
+        self.operations.push(Operation::Remove { path, stash });
+    }
+
+    /// Add a [`Operation::WriteConfig`] replacing the config at `path` with `config`.
+    pub fn write_config(
+        &mut self,
+        path: &Path,
+        config: &DotConfig,
+    ) -> Result<(), toml::ser::Error> {
+        self.operations.push(Operation::WriteConfig {
+            path: path.to_path_buf(),
+            content: config.to_string()?,
+            previous: fs::read_to_string(path).ok(),
+        });
+        Ok(())
+    }
+
+    /// Apply every operation in order.
+    ///
+    /// If an operation fails, the already applied ones are rolled back in
+    /// reverse order. A journaled plan records each step before taking it,
+    /// and the journal is only kept if rolling back failed as well.
+    pub fn apply(&self) -> Result<(), ApplyError> {
+        let journal = self.journal.as_ref().map(Journal::new);
+        if let Some(journal) = &journal {
+            if journal.exists() {
+                return Err(ApplyError::Interrupted(journal.path().to_path_buf()));
+            }
+            journal
+                .record(&self.operations, 0)
+                .map_err(ApplyError::JournalError)?;
+        }
+        for (i, op) in self.operations.iter().enumerate() {
+            let result = match &journal {
+                Some(journal) => journal.record(&self.operations, i + 1),
+                None => Ok(()),
+            };
+            if let Err(err) = result.and_then(|_| op.apply()) {
+                if let Err(rollback_error) = self.rollback(i) {
+                    return Err(ApplyError::RollbackError {
+                        original_error: err,
+                        rollback_error,
+                    });
+                }
+                self.clear_journal(journal.as_ref())?;
+                return match self.entry_of(i) {
+                    Some(failed) => Err(ApplyError::EntryFailed {
+                        error: err,
+                        failed: failed.clone(),
+                        reverted: self
+                            .entries
+                            .iter()
+                            .filter(|(start, source)| *start < i && source != failed)
+                            .map(|(_, source)| source.clone())
+                            .collect(),
+                    }),
+                    None => Err(ApplyError::IO(err)),
+                };
+            }
+        }
+        for op in self.operations.iter() {
+            if let Err(err) = op.finalize() {
+                let path = match op {
+                    Operation::Remove { path, .. } => path.clone(),
+                    _ => PathBuf::new(),
+                };
+                return Err(ApplyError::CleanupError(path, err));
+            }
+        }
+        self.clear_journal(journal.as_ref())
+    }
+
+    fn clear_journal(&self, journal: Option<&Journal>) -> Result<(), ApplyError> {
+        match journal {
+            Some(journal) => journal.clear().map_err(ApplyError::JournalError),
+            None => Ok(()),
+        }
+    }
+
+    /// Roll back the first `applied` operations, last one first.
+    fn rollback(&self, applied: usize) -> io::Result<()> {
+        for op in self.operations[..applied].iter().rev() {
+            op.rollback()?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.operations.is_empty() && self.skipped.is_empty() {
+            return writeln!(f, "nothing to do");
+        }
+        for op in self.operations.iter() {
+            writeln!(f, "{op}")?;
+        }
+        for (source, reason) in self.skipped.iter() {
+            writeln!(f, "skip {}: {reason}", source.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::root_dir, utils::AbsPath};
+    use rstest::rstest;
+
+    #[rstest]
+    fn plan_rollback(root_dir: &AbsPath) {
+        let test_dir = root_dir.join("plan_rollback");
+        fs::create_dir(&test_dir).unwrap();
+        let file = test_dir.join("file");
+        fs::write(&file, "content").unwrap();
+        let mut plan = Plan::new();
+        plan.create_parents(&test_dir.join("a/b/file"));
+        plan.push(Operation::Move {
+            from: file.clone(),
+            to: test_dir.join("a/b/file"),
+        });
+        plan.push(Operation::Symlink {
+            link: file.clone(),
+            target: test_dir.join("a/b/file"),
+        });
+        plan.remove(test_dir.join("does-not-exist"));
+        assert_eq!(plan.operations().len(), 5);
+        assert!(matches!(plan.apply(), Err(ApplyError::IO(_))));
+        assert!(!file.is_symlink());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        assert!(!test_dir.join("a").exists());
+    }
+
+    #[rstest]
+    fn plan_remove(root_dir: &AbsPath) {
+        let test_dir = root_dir.join("plan_remove");
+        fs::create_dir_all(test_dir.join("dir")).unwrap();
+        fs::write(test_dir.join("dir/file"), "content").unwrap();
+        let mut plan = Plan::new();
+        fs::write(test_dir.join("dir.dotman-removed"), "unrelated").unwrap();
+        plan.remove(test_dir.join("dir"));
+        let stash = test_dir.join("dir.dotman-removed.1");
+        assert_eq!(
+            plan.operations(),
+            &[Operation::Remove {
+                path: test_dir.join("dir"),
+                stash: stash.clone(),
+            }]
+        );
+        plan.apply().unwrap();
+        assert!(!test_dir.join("dir").exists());
+        assert!(!stash.exists());
+        assert_eq!(
+            fs::read_to_string(test_dir.join("dir.dotman-removed")).unwrap(),
+            "unrelated"
+        );
+    }
+
+    #[rstest]
+    fn plan_move_keeps_existing(root_dir: &AbsPath) {
+        let test_dir = root_dir.join("plan_move_keeps_existing");
+        fs::create_dir(&test_dir).unwrap();
+        let (from, to) = (test_dir.join("from"), test_dir.join("to"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "old").unwrap();
+        let mut plan = Plan::new();
+        plan.push(Operation::Move {
+            from: from.clone(),
+            to: to.clone(),
+        });
+        assert!(
+            matches!(plan.apply(), Err(ApplyError::IO(e)) if e.kind() == io::ErrorKind::AlreadyExists)
+        );
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+
+        let free = plan.free_path(&to);
+        assert_eq!(free, test_dir.join("to.1"));
+        let mut plan = Plan::new();
+        plan.push(Operation::Move { from, to: free });
+        assert_eq!(plan.free_path(&to), test_dir.join("to.2"));
+        plan.apply().unwrap();
+        assert_eq!(fs::read_to_string(test_dir.join("to.1")).unwrap(), "new");
+    }
+}
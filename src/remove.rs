@@ -0,0 +1,130 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{
+    alternate::{active_source, source_family},
+    config::{self, DotConfig},
+    context::Context,
+    plan::{ApplyError, Plan},
+    status::{entry_state, LinkState},
+    template::{Vars, VarsError},
+    types::SourcePath,
+    CONFIG_FILE_NAME,
+};
+
+#[derive(Error, Debug)]
+pub enum RemoveError {
+    #[error("project not initialized")]
+    ProjectNotInitialized,
+    #[error("dotfile not recorded in config")]
+    DotfileNotRecorded,
+    #[error("{0} is not a link to or copy of the dotfile, refusing to remove it")]
+    LinkNotManaged(PathBuf),
+    #[error("could not read dotman config: {0}")]
+    ReadConfigError(#[from] config::ReadError),
+    #[error("{0}")]
+    Vars(#[from] VarsError),
+    #[error("could not serialize dotman config: {0}")]
+    ConfigSerializationError(#[from] toml::ser::Error),
+    #[error("could not remove dotfile: {0}")]
+    Apply(#[from] ApplyError),
+}
+
+/// How much of a dotfile to remove, each mode includes the ones before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RemoveMode {
+    /// Only drop the record from the config.
+    #[default]
+    Record,
+    /// Also delete the symlink in the home directory.
+    Link,
+    /// Also delete the source from the project.
+    Source,
+}
+
+/// Plan to stop tracking `source`.
+pub fn plan_remove(
+    ctx: &Context,
+    source: &SourcePath,
+    mode: RemoveMode,
+) -> Result<Plan, RemoveError> {
+    use RemoveError as E;
+    let project = ctx.project();
+    let home = ctx.home();
+    let config_path = project.join(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        return Err(E::ProjectNotInitialized);
+    }
+    let mut config = DotConfig::from_file(&config_path)?;
+    let entry = match config.dotfiles.remove(source) {
+        Some(v) => v,
+        None => return Err(E::DotfileNotRecorded),
+    };
+    let plain_source = project.join(source);
+    let abs_source = active_source(ctx, source).unwrap_or_else(|| plain_source.clone());
+    let abs_link = home.join(&entry.link);
+    let vars = Vars::load(ctx)?;
+    let remove_link = match entry_state(&abs_link, &abs_source, entry.mode, &vars) {
+        _ if mode < RemoveMode::Link => false,
+        LinkState::Linked
+        | LinkState::Symlinked
+        | LinkState::Copied
+        | LinkState::Hardlinked
+        | LinkState::Rendered => true,
+        LinkState::Absent | LinkState::LinkedViaParentSymlink => false,
+        _ => return Err(E::LinkNotManaged(abs_link)),
+    };
+    let mut plan = Plan::journaled(project);
+    plan.write_config(&config_path, &config)?;
+    if remove_link {
+        plan.remove(abs_link);
+    }
+    if mode == RemoveMode::Source {
+        for path in source_family(&plain_source) {
+            plan.remove(path);
+        }
+    }
+    Ok(plan)
+}
+
+pub fn remove(ctx: &Context, source: &SourcePath, mode: RemoveMode) -> Result<(), RemoveError> {
+    plan_remove(ctx, source, mode)?.apply()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        example::{example_complete_setup_from_structure, get_example_structure},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+
+    #[rstest]
+    fn remove_modes(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("remove_modes")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+
+        remove(&ctx, &f.bashrc.source, RemoveMode::Record).unwrap();
+        assert!(f.home.join(&f.bashrc.link).is_symlink());
+        let config = DotConfig::from_file(&config_path).unwrap();
+        assert!(!config.dotfiles.contains_key(&f.bashrc.source));
+
+        remove(&ctx, &f.nvim.source, RemoveMode::Source).unwrap();
+        assert!(!f.home.join(&f.nvim.link).is_symlink());
+        assert!(!f.dotfiles.join(&f.nvim.source).exists());
+        let config = DotConfig::from_file(&config_path).unwrap();
+        assert!(config.dotfiles.is_empty());
+
+        assert!(matches!(
+            remove(&ctx, &f.nvim.source, RemoveMode::Record),
+            Err(RemoveError::DotfileNotRecorded)
+        ));
+    }
+}
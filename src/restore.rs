@@ -1,110 +1,391 @@
-use std::{fs, io, os::unix::fs as unix_fs, path::Path};
+use std::{collections::BTreeMap, path::Path};
 
 use thiserror::Error;
 
 use crate::{
-    config::{self, DotConfig},
-    types::ProjectPath,
+    alternate::select,
+    config::{self, DotConfig, DotEntry, Mode},
+    context::Context,
+    machine::Machine,
+    plan::{ApplyError, Operation, Plan},
+    status::{entry_state, LinkState},
+    template::{render_file, RenderError, Vars, VarsError},
+    types::{ProjectPath, SourcePath},
     utils::AbsPath,
     CONFIG_FILE_NAME,
 };
 
 #[derive(Error, Debug)]
 pub enum RestoreError {
-    #[error("IO error while restoring dotfile. Successfully rolled back changes. IO-Error: {0}")]
-    IO(#[from] io::Error),
-    #[error(
-        "IO error while adding dotfile. Unsuccessfully rolled back changes.\n Original Error: {original_error}\n Rollback Error: {rollback_error}"
-    )]
-    RollbackError {
-        original_error: io::Error,
-        rollback_error: io::Error,
-    },
+    #[error("could not restore dotfile: {0}")]
+    Apply(#[from] ApplyError),
     #[error("project not initialized")]
     ProjectNotInitialized,
     #[error("coulnd not read dotman config: {0}")]
     ReadConfigError(#[from] config::ReadError),
     #[error("dotfile not found")]
     DotfileNotFound,
+    #[error("dotfile not recorded in config")]
+    DotfileNotRecorded,
     #[error("link occupied")]
     LinkOccupied,
+    #[error("{0}")]
+    Vars(#[from] VarsError),
+    #[error("could not render template: {0}")]
+    Render(#[from] RenderError),
+    #[error("could not serialize dotman config: {0}")]
+    ConfigSerializationError(#[from] toml::ser::Error),
 }
 
-fn raw_restore(abs_source: &Path, abs_link: &Path) -> Result<(), io::Error> {
-    fs::remove_file(abs_link)?;
-    fs::rename(abs_source, abs_link)?;
-    // TODO: Remove entry from config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// Keep the restored dotfiles recorded in the config, so they can be added again later.
+    pub keep_record: bool,
+    /// Replace the links with copies, leaving the project and its config untouched.
+    pub copy: bool,
+}
+
+impl RestoreOptions {
+    fn updates_config(&self) -> bool {
+        !self.keep_record && !self.copy
+    }
+}
+
+/// Whether something other than a link to, or unchanged copy of, the source is at `abs_link`.
+fn link_occupied(abs_link: &Path, abs_source: &Path, entry: &DotEntry, vars: &Vars) -> bool {
+    let state = entry_state(abs_link, abs_source, entry.mode, vars);
+    !abs_link.is_symlink()
+        && abs_link.exists()
+        && !matches!(
+            state,
+            LinkState::Copied | LinkState::Hardlinked | LinkState::Rendered
+        )
+}
+
+/// Plan leaving the rendering of a template at its link path, dropping the template itself.
+///
+/// An up to date rendering is kept, a symlink to the template is replaced by one.
+fn plan_keep_rendered(
+    plan: &mut Plan,
+    abs_source: &Path,
+    abs_link: &Path,
+    vars: &Vars,
+    options: RestoreOptions,
+) -> Result<(), RestoreError> {
+    let render = match entry_state(abs_link, abs_source, Mode::Template, vars) {
+        LinkState::Rendered => false,
+        LinkState::Absent => {
+            plan.create_parents(abs_link);
+            true
+        }
+        _ if abs_link.is_symlink() => {
+            plan.remove(abs_link.to_path_buf());
+            true
+        }
+        _ => return Err(RestoreError::LinkOccupied),
+    };
+    if render {
+        plan.push(Operation::Render {
+            link: abs_link.to_path_buf(),
+            template: abs_source.to_path_buf(),
+            content: render_file(abs_source, vars)?,
+        });
+    }
+    if !options.copy {
+        plan.remove(abs_source.to_path_buf());
+    }
     Ok(())
 }
 
-fn rollback_restore(abs_source: &Path, abs_link: &Path) -> Result<(), io::Error> {
-    if !abs_link.is_symlink() && !abs_link.exists() {
-        unix_fs::symlink(abs_link, abs_source)?;
+fn plan_move_back(plan: &mut Plan, abs_source: &Path, abs_link: &Path, options: RestoreOptions) {
+    if abs_link.is_symlink() || abs_link.exists() {
+        plan.remove(abs_link.to_path_buf());
+    } else {
+        plan.create_parents(abs_link);
     }
+    let from = abs_source.to_path_buf();
+    let to = abs_link.to_path_buf();
+    if options.copy {
+        plan.push(Operation::Copy { from, to });
+    } else {
+        plan.push(Operation::Move { from, to });
+    }
+}
+
+fn plan_entry(
+    plan: &mut Plan,
+    abs_source: &Path,
+    abs_link: &Path,
+    entry: &DotEntry,
+    vars: &Vars,
+    options: RestoreOptions,
+) -> Result<(), RestoreError> {
+    if entry.mode == Mode::Template {
+        return plan_keep_rendered(plan, abs_source, abs_link, vars, options);
+    }
+    plan_move_back(plan, abs_source, abs_link, options);
     Ok(())
 }
 
-fn atomic_restore(abs_source: &Path, abs_link: &Path) -> Result<(), RestoreError> {
-    let result = raw_restore(abs_source, abs_link);
-    if let Err(err) = result {
-        if let Err(rollback_error) = rollback_restore(abs_source, abs_link) {
-            return Err(RestoreError::RollbackError {
-                original_error: err,
-                rollback_error,
-            });
-        }
-        return Err(RestoreError::IO(err));
+fn plan_source(
+    project: &ProjectPath,
+    source: &SourcePath,
+    home: &AbsPath,
+    vars: &Vars,
+    machine: &Machine,
+    options: RestoreOptions,
+) -> Result<Plan, RestoreError> {
+    use RestoreError as E;
+    let config_path = project.join(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        return Err(E::ProjectNotInitialized);
+    }
+    let mut config = DotConfig::from_file(&config_path)?;
+    let entry = match config.dotfiles.remove(source) {
+        Some(v) => v,
+        None => return Err(E::DotfileNotRecorded),
+    };
+    if !machine.applies(&entry) {
+        let mut plan = Plan::journaled(project);
+        plan.skip(source, "not for this machine");
+        return Ok(plan);
     }
-    return Ok(());
+    let abs_source = select(&project.join(source), machine).ok_or(E::DotfileNotFound)?;
+    let abs_link = home.join(&entry.link);
+    if link_occupied(&abs_link, &abs_source, &entry, vars) {
+        return Err(E::LinkOccupied);
+    }
+    let mut plan = Plan::journaled(project);
+    if options.updates_config() {
+        plan.write_config(&config_path, &config)?;
+    }
+    plan_entry(&mut plan, &abs_source, &abs_link, &entry, vars, options)?;
+    Ok(plan)
 }
 
-pub fn restore(project: &ProjectPath, home: &AbsPath) -> Result<(), RestoreError> {
+/// Plan moving every dotfile of the project back to its link path.
+pub fn plan_restore(ctx: &Context, options: RestoreOptions) -> Result<Plan, RestoreError> {
     use RestoreError as E;
+    let project = ctx.project();
+    let home = ctx.home();
     let config_path = project.join(CONFIG_FILE_NAME);
     if !config_path.exists() {
         return Err(E::ProjectNotInitialized);
     }
-    let config = DotConfig::from_file(&config_path)?;
-    for (source, link) in config.dotfiles.iter() {
-        let abs_link = home.join(link);
-        let abs_source = project.join(source);
-        if !abs_source.exists() {
-            return Err(E::DotfileNotFound);
-        }
-        if !abs_link.is_symlink() && abs_link.exists() {
+    let mut config = DotConfig::from_file(&config_path)?;
+    let vars = Vars::load(ctx)?;
+    let machine = Machine::current(ctx);
+    // Entries that do not apply here or are filtered out stay recorded and are left alone.
+    let (restored, kept): (BTreeMap<_, _>, _) = config
+        .dotfiles
+        .into_iter()
+        .partition(|(_, entry)| machine.applies(entry) && ctx.tags().matches(entry));
+    config.dotfiles = kept;
+    for (source, entry) in restored.iter() {
+        let abs_link = home.join(&entry.link);
+        let abs_source = select(&project.join(source), &machine).ok_or(E::DotfileNotFound)?;
+        if link_occupied(&abs_link, &abs_source, entry, &vars) {
             return Err(E::LinkOccupied);
         }
     }
-    for (source, link) in config.dotfiles.iter() {
-        let abs_link = home.join(link);
-        let abs_source = project.join(source);
-        atomic_restore(&abs_source, &abs_link)?;
+    let mut plan = Plan::journaled(project);
+    if options.updates_config() {
+        plan.write_config(&config_path, &config)?;
+    }
+    for (source, entry) in restored.iter() {
+        let abs_link = home.join(&entry.link);
+        let abs_source = select(&project.join(source), &machine).ok_or(E::DotfileNotFound)?;
+        plan.begin_entry(source);
+        plan_entry(&mut plan, &abs_source, &abs_link, entry, &vars, options)?;
     }
+    Ok(plan)
+}
+
+pub fn restore(ctx: &Context, options: RestoreOptions) -> Result<(), RestoreError> {
+    plan_restore(ctx, options)?.apply()?;
+    Ok(())
+}
+
+/// Plan moving a single recorded dotfile back to its link path.
+pub fn plan_restore_dotfile(
+    ctx: &Context,
+    source: &SourcePath,
+    options: RestoreOptions,
+) -> Result<Plan, RestoreError> {
+    let vars = Vars::load(ctx)?;
+    let machine = Machine::current(ctx);
+    plan_source(ctx.project(), source, ctx.home(), &vars, &machine, options)
+}
+
+pub fn restore_dotfile(
+    ctx: &Context,
+    source: &SourcePath,
+    options: RestoreOptions,
+) -> Result<(), RestoreError> {
+    plan_restore_dotfile(ctx, source, options)?.apply()?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-
     use super::*;
     use crate::{
+        config::TagFilter,
         example::{example_complete_setup_from_structure, get_example_structure},
+        template::LOCAL_VARS_FILE_NAME,
         tests::root_dir,
+        utils::AbsPath,
     };
     use rstest::rstest;
+    use std::{fs, os::unix::fs::PermissionsExt};
 
     #[rstest]
-    fn basic_restore(root_dir: &PathBuf) {
+    fn basic_restore(root_dir: &AbsPath) {
         let test_dir = AbsPath::new(root_dir.join("basic_restore")).unwrap();
         let f = get_example_structure(&test_dir, &test_dir, &test_dir);
         example_complete_setup_from_structure(&f).unwrap();
-        restore(&f.dotfiles, &f.home).expect("restoring to work");
+        restore(&f.context(), RestoreOptions::default()).expect("restoring to work");
         assert!(!&f.home.join(&f.bashrc.link).is_symlink());
         assert!(&f.home.join(&f.bashrc.link).exists());
         assert!(!&f.dotfiles.join(&f.bashrc.source).exists());
         assert!(!&f.home.join(&f.nvim.link).is_symlink());
         assert!(&f.home.join(&f.nvim.link).exists());
         assert!(!&f.dotfiles.join(&f.nvim.source).exists());
+        let config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        assert!(config.dotfiles.is_empty());
+    }
+
+    #[rstest]
+    fn restore_single_dotfile(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("restore_single_dotfile")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        restore_dotfile(&f.context(), &f.bashrc.source, RestoreOptions::default())
+            .expect("restoring to work");
+        assert!(!&f.home.join(&f.bashrc.link).is_symlink());
+        assert!(&f.home.join(&f.bashrc.link).exists());
+        assert!(!&f.dotfiles.join(&f.bashrc.source).exists());
+        assert!(&f.home.join(&f.nvim.link).is_symlink());
+        let config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        assert!(!config.dotfiles.contains_key(&f.bashrc.source));
+        assert!(config.dotfiles.contains_key(&f.nvim.source));
+        assert!(matches!(
+            restore_dotfile(&f.context(), &f.bashrc.source, RestoreOptions::default()),
+            Err(RestoreError::DotfileNotRecorded)
+        ));
+    }
+
+    #[rstest]
+    fn restore_copy(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("restore_copy")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let init_lua = f.dotfiles.join(&f.nvim.source).join("init.lua");
+        fs::set_permissions(&init_lua, fs::Permissions::from_mode(0o600)).unwrap();
+        let options = RestoreOptions {
+            copy: true,
+            ..Default::default()
+        };
+        restore(&f.context(), options).expect("restoring to work");
+        let nvim_link = f.home.join(&f.nvim.link);
+        assert!(!nvim_link.is_symlink());
+        assert!(nvim_link.join("init.lua").exists());
+        let mode = fs::metadata(nvim_link.join("init.lua"))
+            .unwrap()
+            .permissions();
+        assert_eq!(mode.mode() & 0o777, 0o600);
+        assert!(!&f.home.join(&f.bashrc.link).is_symlink());
+        assert!(&f.dotfiles.join(&f.bashrc.source).exists());
+        assert!(init_lua.exists());
+        let config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(config.dotfiles.len(), 2);
+    }
+
+    #[rstest]
+    fn restore_symlinked_template(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("restore_symlinked_template")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        config.dotfiles.get_mut(&f.bashrc.source).unwrap().mode = Mode::Template;
+        config.write(&config_path).unwrap();
+        fs::write(
+            f.dotfiles.join(LOCAL_VARS_FILE_NAME),
+            "[vars]\nemail = \"me@home\"\n",
+        )
+        .unwrap();
+        fs::write(f.dotfiles.join(&f.bashrc.source), "EMAIL={{ email }}\n").unwrap();
+
+        restore_dotfile(&ctx, &f.bashrc.source, RestoreOptions::default()).unwrap();
+        let link = f.home.join(&f.bashrc.link);
+        assert!(!link.is_symlink());
+        assert_eq!(fs::read_to_string(&link).unwrap(), "EMAIL=me@home\n");
+        assert!(!f.dotfiles.join(&f.bashrc.source).exists());
+        let config = DotConfig::from_file(&config_path).unwrap();
+        assert!(!config.dotfiles.contains_key(&f.bashrc.source));
+    }
+
+    #[rstest]
+    fn restore_keep_record(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("restore_keep_record")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let options = RestoreOptions {
+            keep_record: true,
+            ..Default::default()
+        };
+        restore(&f.context(), options).expect("restoring to work");
+        assert!(!&f.home.join(&f.bashrc.link).is_symlink());
+        let config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(config.dotfiles.len(), 2);
+    }
+
+    #[rstest]
+    fn restore_by_tag(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("restore_by_tag")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        for (entry, tags) in config.dotfiles.values_mut().zip([["shell"], ["editor"]]) {
+            entry.tags = tags.iter().map(|t| t.to_string()).collect();
+        }
+        config.write(&config_path).unwrap();
+        let with_tags = |tags: TagFilter| {
+            Context::builder()
+                .home(f.home.to_path_buf())
+                .cwd(f.home.to_path_buf())
+                .project(f.dotfiles.to_path_buf())
+                .tags(tags)
+                .build()
+                .unwrap()
+        };
+
+        let editor = with_tags(TagFilter {
+            include: vec!["editor".to_string()],
+            ..Default::default()
+        });
+        restore(&editor, RestoreOptions::default()).unwrap();
+        assert!(!f.home.join(&f.nvim.link).is_symlink());
+        assert!(f.home.join(&f.nvim.link).is_dir());
+        assert!(f.home.join(&f.bashrc.link).is_symlink());
+        let config = DotConfig::from_file(&config_path).unwrap();
+        assert_eq!(
+            config.dotfiles.keys().collect::<Vec<_>>(),
+            [&f.bashrc.source]
+        );
+
+        let no_shell = with_tags(TagFilter {
+            exclude: vec!["shell".to_string()],
+            ..Default::default()
+        });
+        assert!(plan_restore(&no_shell, RestoreOptions::default())
+            .unwrap()
+            .operations()
+            .iter()
+            .all(|op| matches!(op, Operation::WriteConfig { .. })));
+        assert!(f.home.join(&f.bashrc.link).is_symlink());
     }
 }
@@ -1,13 +1,26 @@
-use std::{fs, io, os::unix::fs as unix_fs, path::Path};
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    config::{DotConfig, ReadError},
+    alternate::select,
+    config::{DotConfig, DotEntry, Mode, ReadError},
+    context::Context,
+    machine::Machine,
+    plan::{ApplyError, Operation, Plan},
+    status::{entry_state, LinkState},
+    template::{render_file, RenderError, Vars, VarsError},
     types::{ProjectPath, SourcePath},
     utils::AbsPath,
     CONFIG_FILE_NAME,
 };
 use thiserror::Error;
 
+/// Backups of files replaced by setup, relative to the project root.
+pub const BACKUP_DIR_NAME: &str = ".dotman/backups";
+
 #[derive(Error, Debug)]
 pub enum SetupError {
     #[error("project not initialized")]
@@ -20,95 +33,293 @@ pub enum SetupError {
     DotfileNotRecorded,
     #[error("link path is already occupied")]
     LinkOccupied,
-    #[error("error while restoring source: {0}")]
-    SetupError(#[from] io::Error),
+    #[error("cannot hard link directory {0}")]
+    HardlinkDirectory(PathBuf),
+    #[error(
+        "cannot hard link {} to {}, they are on different filesystems",
+        .link.display(),
+        .target.display()
+    )]
+    CrossDevice { target: PathBuf, link: PathBuf },
+    #[error("cannot adopt {0}, it is a symlink")]
+    AdoptSymlink(PathBuf),
+    #[error("cannot adopt {0}, it would replace a template with its rendering")]
+    AdoptTemplate(PathBuf),
+    #[error("{0}")]
+    Vars(#[from] VarsError),
+    #[error("could not render template: {0}")]
+    Render(#[from] RenderError),
+    #[error("could not setup dotfile: {0}")]
+    Apply(#[from] ApplyError),
+}
+
+/// What to do with something already in place of a link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Fail without changing anything.
+    #[default]
+    Fail,
+    /// Leave the dotfile unlinked.
+    Skip,
+    /// Move the existing file to a timestamped backup in the project.
+    Backup,
+    /// Delete the existing file.
+    Overwrite,
+    /// Move the existing file into the project as the new source.
+    Adopt,
 }
 
-fn atomic_setup(link_source: &Path, link_target: &Path) -> Result<(), io::Error> {
-    if let Some(parent) = link_source.parent() {
-        fs::create_dir_all(parent)?;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetupOptions {
+    pub on_conflict: OnConflict,
+}
+
+/// Refuse hard links to directories or across filesystems, which would only fail when applied.
+fn check_hardlink(abs_link: &Path, abs_source: &Path) -> Result<(), SetupError> {
+    if abs_source.is_dir() {
+        return Err(SetupError::HardlinkDirectory(abs_source.to_path_buf()));
+    }
+    let existing_parent = abs_link.ancestors().skip(1).find(|p| p.exists());
+    let same_device = match (fs::metadata(abs_source), existing_parent.map(fs::metadata)) {
+        (Ok(source), Some(Ok(parent))) => source.dev() == parent.dev(),
+        _ => true,
+    };
+    if !same_device {
+        return Err(SetupError::CrossDevice {
+            target: abs_source.to_path_buf(),
+            link: abs_link.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+/// Plan putting `abs_source` in place at `abs_link` the way `mode` says.
+pub(crate) fn plan_link(
+    plan: &mut Plan,
+    abs_link: &Path,
+    abs_source: &Path,
+    mode: Mode,
+    vars: &Vars,
+) -> Result<(), SetupError> {
+    if mode == Mode::Hardlink {
+        check_hardlink(abs_link, abs_source)?;
+    }
+    plan.create_parents(abs_link);
+    let link = abs_link.to_path_buf();
+    let source = abs_source.to_path_buf();
+    match mode {
+        Mode::Symlink => plan.push(Operation::Symlink {
+            link,
+            target: source,
+        }),
+        Mode::Copy => plan.push(Operation::Copy {
+            from: source,
+            to: link,
+        }),
+        Mode::Hardlink => plan.push(Operation::Hardlink {
+            link,
+            target: source,
+        }),
+        Mode::Template => plan.push(Operation::Render {
+            content: render_file(&source, vars)?,
+            link,
+            template: source,
+        }),
     }
-    unix_fs::symlink(&link_target, &link_source)?;
     Ok(())
 }
 
-fn setup_source(
+/// What every dotfile of a single setup is planned with.
+struct SetupRun {
+    backup_dir: PathBuf,
+    vars: Vars,
+    machine: Machine,
+    options: SetupOptions,
+}
+
+impl SetupRun {
+    fn new(ctx: &Context, options: SetupOptions) -> Result<Self, SetupError> {
+        Ok(Self {
+            backup_dir: backup_dir(ctx.project()),
+            vars: Vars::load(ctx)?,
+            machine: Machine::current(ctx),
+            options,
+        })
+    }
+}
+
+/// Plan linking a single dotfile, resolving a conflict at the link as `options` say.
+fn plan_entry(
+    plan: &mut Plan,
+    source: &SourcePath,
+    entry: &DotEntry,
+    abs_link: &Path,
+    abs_source: &Path,
+    run: &SetupRun,
+) -> Result<(), SetupError> {
+    let state = entry_state(abs_link, abs_source, entry.mode, &run.vars);
+    match state {
+        LinkState::Copied => plan.skip(source, "already copied"),
+        LinkState::Rendered => plan.skip(source, "already rendered"),
+        _ if state.is_linked() => plan.skip(source, "already linked"),
+        _ => {}
+    }
+    if state.is_linked() {
+        return Ok(());
+    }
+    let existing = abs_link.to_path_buf();
+    // A symlink to the source in another mode is replaced without asking.
+    let conflict = !matches!(state, LinkState::Absent | LinkState::Symlinked);
+    match run.options.on_conflict {
+        _ if !conflict => {
+            plan.begin_entry(source);
+            if state == LinkState::Symlinked {
+                plan.remove(existing);
+            }
+        }
+        OnConflict::Fail => return Err(SetupError::LinkOccupied),
+        OnConflict::Skip => {
+            plan.skip(source, "link occupied");
+            return Ok(());
+        }
+        OnConflict::Backup => {
+            let backup = plan.free_path(&run.backup_dir.join(source));
+            plan.begin_entry(source);
+            plan.create_parents(&backup);
+            plan.push(Operation::Move {
+                from: existing,
+                to: backup,
+            });
+        }
+        OnConflict::Overwrite => {
+            plan.begin_entry(source);
+            plan.remove(existing);
+        }
+        OnConflict::Adopt => {
+            if abs_link.is_symlink() {
+                return Err(SetupError::AdoptSymlink(existing));
+            }
+            if entry.mode == Mode::Template {
+                return Err(SetupError::AdoptTemplate(existing));
+            }
+            plan.begin_entry(source);
+            plan.remove(abs_source.to_path_buf());
+            plan.push(Operation::Move {
+                from: existing,
+                to: abs_source.to_path_buf(),
+            });
+        }
+    }
+    plan_link(plan, abs_link, abs_source, entry.mode, &run.vars)
+}
+
+/// Directory backups of this run go to.
+fn backup_dir(project: &ProjectPath) -> PathBuf {
+    let time_format = "%Y-%m-%d_%H-%M-%S";
+    let current_time = chrono::offset::Local::now();
+    project
+        .join(BACKUP_DIR_NAME)
+        .join(current_time.format(time_format).to_string())
+}
+
+fn plan_source(
     project: &ProjectPath,
     source: &SourcePath,
     home: &AbsPath,
-) -> Result<(), SetupError> {
+    run: &SetupRun,
+) -> Result<Plan, SetupError> {
     use SetupError as E;
     let config_path = project.join(CONFIG_FILE_NAME);
     if !config_path.exists() {
         return Err(E::ProjectNotInitialized);
     }
-    let abs_source = project.join(source);
-    if !abs_source.exists() {
-        return Err(E::DotfileNotFound);
-    }
     let config = DotConfig::from_file(config_path)?;
-    let link = match config.dotfiles.get(source) {
+    let entry = match config.dotfiles.get(source) {
         Some(v) => v,
         None => return Err(E::DotfileNotRecorded),
     };
-    let abs_link = home.join(link);
-    if abs_link.is_symlink() || abs_link.exists() {
-        return Err(E::LinkOccupied);
+    let mut plan = Plan::journaled(project);
+    if !run.machine.applies(entry) {
+        plan.skip(source, "not for this machine");
+        return Ok(plan);
     }
-    atomic_setup(&abs_link, &abs_source)?;
-    Ok(())
+    let abs_source = select(&project.join(source), &run.machine).ok_or(E::DotfileNotFound)?;
+    let abs_link = home.join(&entry.link);
+    plan_entry(&mut plan, source, entry, &abs_link, &abs_source, run)?;
+    Ok(plan)
 }
 
-pub fn setup_project(project: &ProjectPath, home: &AbsPath) -> Result<(), SetupError> {
+/// Plan linking every dotfile recorded in the project.
+pub fn plan_setup_project(ctx: &Context, options: SetupOptions) -> Result<Plan, SetupError> {
     use SetupError as E;
+    let project = ctx.project();
+    let home = ctx.home();
     let config_path = project.join(CONFIG_FILE_NAME);
     if !config_path.exists() {
         return Err(E::ProjectNotInitialized);
     }
     let config = DotConfig::from_file(config_path)?;
-    for (source, link) in config.dotfiles.iter() {
-        let abs_link = home.join(link);
-        let abs_source = project.join(source);
-        if !abs_source.exists() {
-            return Err(E::DotfileNotFound);
+    let run = SetupRun::new(ctx, options)?;
+    let mut plan = Plan::journaled(project);
+    for (source, entry) in config.dotfiles.iter() {
+        if !ctx.tags().matches(entry) {
+            continue;
         }
-        if abs_link.is_symlink() || abs_link.exists() {
-            return Err(E::LinkOccupied);
+        if !run.machine.applies(entry) {
+            plan.skip(source, "not for this machine");
+            continue;
         }
+        let abs_link = home.join(&entry.link);
+        let abs_source = select(&project.join(source), &run.machine).ok_or(E::DotfileNotFound)?;
+        plan_entry(&mut plan, source, entry, &abs_link, &abs_source, &run)?;
     }
-    for (source, link) in config.dotfiles.iter() {
-        let abs_link = home.join(link);
-        let abs_source = project.join(source);
-        atomic_setup(&abs_link, &abs_source)?;
-    }
+    Ok(plan)
+}
+
+pub fn setup_project(ctx: &Context, options: SetupOptions) -> Result<(), SetupError> {
+    plan_setup_project(ctx, options)?.apply()?;
     Ok(())
 }
 
+/// Plan linking a single recorded dotfile.
+pub fn plan_setup_dotfile(
+    ctx: &Context,
+    source: &SourcePath,
+    options: SetupOptions,
+) -> Result<Plan, SetupError> {
+    let run = SetupRun::new(ctx, options)?;
+    plan_source(ctx.project(), source, ctx.home(), &run)
+}
+
 pub fn setup_dotfile(
-    project: &ProjectPath,
+    ctx: &Context,
     source: &SourcePath,
-    home: &AbsPath,
+    options: SetupOptions,
 ) -> Result<(), SetupError> {
-    setup_source(project, source, home)
+    plan_setup_dotfile(ctx, source, options)?.apply()?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-
     use super::*;
     use crate::{
+        config::{Condition, TagFilter},
+        diff::{project_diff, Difference},
         example::{example_new_machine_from_structure, get_example_structure},
+        status::project_status,
+        template::LOCAL_VARS_FILE_NAME,
         tests::root_dir,
     };
     use rstest::rstest;
+    use std::fs;
 
     #[rstest]
-    fn basic_setup(root_dir: &PathBuf) {
+    fn basic_setup(root_dir: &AbsPath) {
         let test_dir = AbsPath::new(root_dir.join("basic_setup")).unwrap();
         let f = get_example_structure(&test_dir, &test_dir, &test_dir);
         example_new_machine_from_structure(&f).unwrap();
-        setup_project(&f.dotfiles, &f.home).unwrap();
+        setup_project(&f.context(), SetupOptions::default()).unwrap();
 
         let toml_content = r#"[dotfiles]
 bashrc = "~/bashrc"
@@ -116,7 +327,268 @@ nvim = "~/config/nvim"
 "#;
 
         let expected_config: DotConfig = toml::from_str(toml_content).unwrap();
-        let actual_config = DotConfig::from_file(&f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        let actual_config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
         assert_eq!(actual_config, expected_config);
     }
+
+    #[rstest]
+    fn setup_reverts_all_entries(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_reverts_all_entries")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let plan = plan_setup_project(&f.context(), SetupOptions::default()).unwrap();
+        fs::create_dir_all(f.home.join(&f.nvim.link)).unwrap();
+        match plan.apply() {
+            Err(ApplyError::EntryFailed {
+                failed, reverted, ..
+            }) => {
+                assert_eq!(failed, f.nvim.source);
+                assert_eq!(reverted, vec![f.bashrc.source.clone()]);
+            }
+            other => panic!("expected failed entry, got {:?}", other),
+        }
+        assert!(!f.home.join(&f.bashrc.link).is_symlink());
+    }
+
+    #[rstest]
+    fn setup_skips_linked(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_skips_linked")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        setup_dotfile(&f.context(), &f.bashrc.source, SetupOptions::default()).unwrap();
+        let plan = plan_setup_project(&f.context(), SetupOptions::default()).unwrap();
+        assert_eq!(
+            plan.skipped(),
+            &[(f.bashrc.source.clone(), "already linked".to_string())]
+        );
+        plan.apply().unwrap();
+        assert!(f.home.join(&f.nvim.link).is_symlink());
+
+        fs::remove_file(f.home.join(&f.nvim.link)).unwrap();
+        fs::write(f.home.join(&f.nvim.link), "").unwrap();
+        assert!(matches!(
+            plan_setup_project(&f.context(), SetupOptions::default()),
+            Err(SetupError::LinkOccupied)
+        ));
+    }
+
+    #[rstest]
+    fn setup_on_conflict(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_on_conflict")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let bashrc_link = f.home.join(&f.bashrc.link);
+        fs::write(&bashrc_link, "local").unwrap();
+        let backup = SetupOptions {
+            on_conflict: OnConflict::Backup,
+        };
+        setup_dotfile(&ctx, &f.bashrc.source, backup).unwrap();
+        assert!(bashrc_link.is_symlink());
+        let backups: Vec<_> = fs::read_dir(f.dotfiles.join(BACKUP_DIR_NAME))
+            .unwrap()
+            .collect();
+        assert_eq!(backups.len(), 1);
+        let backup_dir = backups[0].as_ref().unwrap().path();
+        assert_eq!(
+            fs::read_to_string(backup_dir.join(&f.bashrc.source)).unwrap(),
+            "local"
+        );
+
+        fs::remove_file(&bashrc_link).unwrap();
+        fs::write(&bashrc_link, "second").unwrap();
+        let plan = plan_setup_dotfile(&ctx, &f.bashrc.source, backup).unwrap();
+        let second_backup = match &plan.operations()[0] {
+            Operation::Move { to, .. } => to.clone(),
+            op => panic!("expected move to backup, got {op}"),
+        };
+        assert_ne!(second_backup, backup_dir.join(&f.bashrc.source));
+        plan.apply().unwrap();
+        assert_eq!(fs::read_to_string(second_backup).unwrap(), "second");
+        assert_eq!(
+            fs::read_to_string(backup_dir.join(&f.bashrc.source)).unwrap(),
+            "local"
+        );
+
+        fs::remove_file(&bashrc_link).unwrap();
+        fs::write(&bashrc_link, "adopted").unwrap();
+        let adopt = SetupOptions {
+            on_conflict: OnConflict::Adopt,
+        };
+        setup_dotfile(&ctx, &f.bashrc.source, adopt).unwrap();
+        assert!(bashrc_link.is_symlink());
+        let source = f.dotfiles.join(&f.bashrc.source);
+        assert_eq!(fs::read_to_string(source).unwrap(), "adopted");
+    }
+
+    #[rstest]
+    fn setup_replaces_symlink(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_replaces_symlink")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        setup_project(&ctx, SetupOptions::default()).unwrap();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        config.dotfiles.get_mut(&f.bashrc.source).unwrap().mode = Mode::Copy;
+        config.write(&config_path).unwrap();
+
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::Symlinked);
+        assert!(!status.is_clean());
+        setup_project(&ctx, SetupOptions::default()).unwrap();
+        let link = f.home.join(&f.bashrc.link);
+        assert!(!link.is_symlink() && link.is_file());
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::Copied);
+    }
+
+    #[rstest]
+    fn setup_hardlink(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_hardlink")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        for entry in config.dotfiles.values_mut() {
+            entry.mode = Mode::Hardlink;
+        }
+        config.write(&config_path).unwrap();
+        assert!(matches!(
+            plan_setup_project(&ctx, SetupOptions::default()),
+            Err(SetupError::HardlinkDirectory(_))
+        ));
+
+        setup_dotfile(&ctx, &f.bashrc.source, SetupOptions::default()).unwrap();
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::Hardlinked);
+
+        let link = f.home.join(&f.bashrc.link);
+        fs::remove_file(&link).unwrap();
+        fs::write(&link, "saved by an editor").unwrap();
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::HardlinkBroken);
+    }
+
+    #[rstest]
+    fn setup_template(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_template")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        config.dotfiles.get_mut(&f.bashrc.source).unwrap().mode = Mode::Template;
+        config.write(&config_path).unwrap();
+        let vars_path = f.dotfiles.join(LOCAL_VARS_FILE_NAME);
+        fs::write(&vars_path, "[vars]\nemail = \"me@home\"\n").unwrap();
+        fs::write(f.dotfiles.join(&f.bashrc.source), "EMAIL={{ email }}\n").unwrap();
+
+        setup_dotfile(&ctx, &f.bashrc.source, SetupOptions::default()).unwrap();
+        let link = f.home.join(&f.bashrc.link);
+        assert_eq!(fs::read_to_string(&link).unwrap(), "EMAIL=me@home\n");
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::Rendered);
+        assert!(project_diff(&ctx, Some(&f.bashrc.source))
+            .unwrap()
+            .is_empty());
+
+        fs::write(&vars_path, "[vars]\nemail = \"me@work\"\n").unwrap();
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::RenderStale);
+        let diffs = project_diff(&ctx, Some(&f.bashrc.source)).unwrap();
+        assert!(matches!(
+            &diffs[0].difference,
+            Difference::Text(d) if d.contains("-EMAIL=me@work\n+EMAIL=me@home\n")
+        ));
+        let adopt = SetupOptions {
+            on_conflict: OnConflict::Adopt,
+        };
+        assert!(matches!(
+            plan_setup_dotfile(&ctx, &f.bashrc.source, adopt),
+            Err(SetupError::AdoptTemplate(_))
+        ));
+        let overwrite = SetupOptions {
+            on_conflict: OnConflict::Overwrite,
+        };
+        setup_dotfile(&ctx, &f.bashrc.source, overwrite).unwrap();
+        assert_eq!(fs::read_to_string(&link).unwrap(), "EMAIL=me@work\n");
+    }
+
+    #[rstest]
+    fn setup_profiles(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_profiles")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        config.dotfiles.get_mut(&f.nvim.source).unwrap().when = Condition {
+            profiles: vec!["personal".to_string()],
+            ..Default::default()
+        };
+        config.write(&config_path).unwrap();
+
+        let ctx = f.context();
+        let plan = plan_setup_project(&ctx, SetupOptions::default()).unwrap();
+        assert_eq!(
+            plan.skipped(),
+            &[(f.nvim.source.clone(), "not for this machine".to_string())]
+        );
+        plan.apply().unwrap();
+        assert!(!f.home.join(&f.nvim.link).exists());
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles.len(), 1);
+        assert!(status.is_clean());
+
+        let ctx = Context::builder()
+            .home(f.home.to_path_buf())
+            .cwd(f.home.to_path_buf())
+            .project(f.dotfiles.to_path_buf())
+            .profile("personal")
+            .build()
+            .unwrap();
+        setup_project(&ctx, SetupOptions::default()).unwrap();
+        assert!(f.home.join(&f.nvim.link).is_symlink());
+        assert_eq!(project_status(&ctx).unwrap().dotfiles.len(), 2);
+    }
+
+    #[rstest]
+    fn setup_by_tag(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("setup_by_tag")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        for (entry, tags) in config.dotfiles.values_mut().zip([["shell"], ["editor"]]) {
+            entry.tags = tags.iter().map(|t| t.to_string()).collect();
+        }
+        config.write(&config_path).unwrap();
+        let with_tags = |tags: TagFilter| {
+            Context::builder()
+                .home(f.home.to_path_buf())
+                .cwd(f.home.to_path_buf())
+                .project(f.dotfiles.to_path_buf())
+                .tags(tags)
+                .build()
+                .unwrap()
+        };
+
+        let shell = with_tags(TagFilter {
+            include: vec!["shell".to_string()],
+            ..Default::default()
+        });
+        setup_project(&shell, SetupOptions::default()).unwrap();
+        assert!(f.home.join(&f.bashrc.link).is_symlink());
+        assert!(!f.home.join(&f.nvim.link).exists());
+
+        let no_shell = with_tags(TagFilter {
+            exclude: vec!["shell".to_string()],
+            ..Default::default()
+        });
+        let plan = plan_setup_project(&no_shell, SetupOptions::default()).unwrap();
+        assert!(plan.skipped().is_empty());
+        plan.apply().unwrap();
+        assert!(f.home.join(&f.nvim.link).is_symlink());
+    }
 }
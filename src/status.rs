@@ -1,9 +1,18 @@
-use std::fs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Serialize, Serializer};
 
 use crate::{
-    config::{self, DotConfig},
-    types::ProjectPath,
-    utils::AbsPath,
+    alternate::select,
+    config::{self, DotConfig, Mode},
+    context::Context,
+    machine::Machine,
+    template::{render_file, Vars, VarsError},
+    types::{LinkPath, ProjectPath, SourcePath},
+    utils::{content_hash, resolve_path, same_inode},
     CONFIG_FILE_NAME,
 };
 
@@ -15,52 +24,490 @@ pub enum StatusError {
     ProjectNotInitialilzed,
     #[error("coulnd not read dotman config: {0}")]
     ReadConfigError(#[from] config::ReadError),
+    #[error("{0}")]
+    Vars(#[from] VarsError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceState {
+    Present,
+    Missing,
+}
+
+/// What was found at the link path of a dotfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    /// Symlink pointing at the project source.
+    Linked,
+    /// Nothing at the link path.
+    Absent,
+    /// A regular file is in the way of the link.
+    OccupiedByFile,
+    /// A directory is in the way of the link.
+    OccupiedByDir,
+    /// Symlink pointing at an existing path outside the source.
+    WrongTarget(PathBuf),
+    /// Symlink pointing at a path that does not exist.
+    Dangling,
+    /// Not a symlink itself, but resolves to the source through a symlinked parent directory.
+    LinkedViaParentSymlink,
+    /// Copy with the same content as the source.
+    Copied,
+    /// Copy whose content differs from the source.
+    CopyDiffers,
+    /// Hard link to the source.
+    Hardlinked,
+    /// Regular file that is no longer a hard link to the source.
+    HardlinkBroken,
+    /// File with the current rendering of the source template.
+    Rendered,
+    /// File that differs from the current rendering of the source template.
+    RenderStale,
+    /// Symlink to the source, where the mode asks for a copy, hard link or rendering.
+    Symlinked,
+}
+
+impl LinkState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinkState::Linked => "linked",
+            LinkState::Absent => "absent",
+            LinkState::OccupiedByFile => "occupied_by_file",
+            LinkState::OccupiedByDir => "occupied_by_dir",
+            LinkState::WrongTarget(_) => "wrong_target",
+            LinkState::Dangling => "dangling",
+            LinkState::LinkedViaParentSymlink => "linked_via_parent_symlink",
+            LinkState::Copied => "copied",
+            LinkState::CopyDiffers => "copy_differs",
+            LinkState::Hardlinked => "hardlinked",
+            LinkState::HardlinkBroken => "hardlink_broken",
+            LinkState::Rendered => "rendered",
+            LinkState::RenderStale => "render_stale",
+            LinkState::Symlinked => "symlinked",
+        }
+    }
+
+    /// Whether the link path leads to the source, or holds an identical copy of it.
+    pub fn is_linked(&self) -> bool {
+        matches!(
+            self,
+            LinkState::Linked
+                | LinkState::LinkedViaParentSymlink
+                | LinkState::Copied
+                | LinkState::Hardlinked
+                | LinkState::Rendered
+        )
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            LinkState::Linked => "linked".to_string(),
+            LinkState::Absent => "missing link".to_string(),
+            LinkState::OccupiedByFile => "link occupied by file".to_string(),
+            LinkState::OccupiedByDir => "link occupied by directory".to_string(),
+            LinkState::WrongTarget(target) => format!("link points to {}", target.display()),
+            LinkState::Dangling => "dangling link".to_string(),
+            LinkState::LinkedViaParentSymlink => "linked via parent symlink".to_string(),
+            LinkState::Copied => "copied".to_string(),
+            LinkState::CopyDiffers => "copy differs from source".to_string(),
+            LinkState::Hardlinked => "hardlinked".to_string(),
+            LinkState::HardlinkBroken => "hardlink broken".to_string(),
+            LinkState::Rendered => "rendered".to_string(),
+            LinkState::RenderStale => "rendering stale".to_string(),
+            LinkState::Symlinked => "symlinked instead of put in place by its mode".to_string(),
+        }
+    }
+}
+
+impl Serialize for LinkState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DotfileStatus {
+    pub source: SourcePath,
+    pub link: LinkPath,
+    pub mode: Mode,
+    /// Alternate of the source in use on this machine, if the source itself does not exist.
+    pub alternate: Option<SourcePath>,
+    pub source_state: SourceState,
+    pub link_state: LinkState,
+    /// Where the symlink at the link path actually points, if there is one.
+    pub link_target: Option<PathBuf>,
+}
+
+/// Overall result of a status check, ordered by severity.
+///
+/// The discriminant is the exit code of `dotman status`, so checking several
+/// projects exits with the most severe outcome among them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatusOutcome {
+    /// Every dotfile has its source and a correct link.
+    Clean = 0,
+    /// At least one dotfile is missing its source or link.
+    Drift = 1,
+    /// A project could not be checked at all.
+    Error = 2,
+}
+
+impl StatusOutcome {
+    pub fn exit_code(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectStatus {
+    pub project: ProjectPath,
+    pub dotfiles: Vec<DotfileStatus>,
+}
+
+impl DotfileStatus {
+    pub fn is_complete(&self) -> bool {
+        self.source_state == SourceState::Present && self.link_state.is_linked()
+    }
+
+    fn summary(&self) -> String {
+        let mut problems = vec![];
+        if self.source_state != SourceState::Present {
+            problems.push("missing source".to_string());
+        }
+        match &self.link_state {
+            state if state.is_linked() && *state != LinkState::LinkedViaParentSymlink => {}
+            LinkState::LinkedViaParentSymlink if problems.is_empty() => {
+                return "Complete, linked via parent symlink".to_string();
+            }
+            state => problems.push(state.describe()),
+        }
+        let summary = match problems.as_slice() {
+            [] => "complete".to_string(),
+            [problem] => problem.clone(),
+            [init @ .., last] => format!("{}, and {last}", init.join(", ")),
+        };
+        let mut chars = summary.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => summary,
+        }
+    }
+}
+
+impl ProjectStatus {
+    pub fn is_clean(&self) -> bool {
+        self.dotfiles.iter().all(DotfileStatus::is_complete)
+    }
+
+    pub fn outcome(&self) -> StatusOutcome {
+        if self.is_clean() {
+            StatusOutcome::Clean
+        } else {
+            StatusOutcome::Drift
+        }
+    }
+
+    /// Human readable summary, one line per dotfile below the project name.
+    pub fn to_human(&self) -> String {
+        let name = self
+            .project
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut out = format!("[{name}]\n");
+        for dotfile in self.dotfiles.iter() {
+            let alternate = dotfile
+                .alternate
+                .as_ref()
+                .map_or(String::new(), |a| format!(" ({})", a.display()));
+            out.push_str(&format!(
+                " {}{}: {}\n",
+                dotfile.source.display(),
+                alternate,
+                dotfile.summary()
+            ));
+        }
+        out
+    }
+
+    /// Stable, line based output for scripts.
+    ///
+    /// One line per dotfile with the tab separated fields
+    /// `project source link source_state link_state link_target`,
+    /// where a missing link target is written as `-`.
+    pub fn to_porcelain(&self) -> String {
+        let mut out = String::new();
+        for dotfile in self.dotfiles.iter() {
+            let link_target = dotfile
+                .link_target
+                .as_ref()
+                .map_or("-".to_string(), |t| t.display().to_string());
+            out.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\n",
+                self.project.display(),
+                dotfile.source.display(),
+                serialized_str(&dotfile.link),
+                serialized_str(&dotfile.source_state),
+                dotfile.link_state.name(),
+                link_target,
+            ));
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn serialized_str<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
 }
 
-pub fn project_summary(project: &ProjectPath, home: &AbsPath) -> Result<(), StatusError> {
+pub fn project_status(ctx: &Context) -> Result<ProjectStatus, StatusError> {
     use StatusError as E;
+    let project = ctx.project();
+    let home = ctx.home();
     let abs_config = project.join(CONFIG_FILE_NAME);
     if !abs_config.exists() {
         return Err(E::ProjectNotInitialilzed);
     }
     let config = DotConfig::from_file(&abs_config)?;
-    println!("[{}]", project.file_name().unwrap().to_str().unwrap());
-    for (source, link) in config.dotfiles.iter() {
-        let mut missings = vec![];
-        if !project.join(source).exists() {
-            missings.push("source");
-        }
-        if !home.join(link).is_symlink() {
-            missings.push("link");
-        }
-        if fs::read_link(home.join(link)).map_or(false, |p| p != project.join(source)) {
-            missings.push("link");
+    let vars = Vars::load(ctx)?;
+    let machine = Machine::current(ctx);
+    let mut dotfiles = vec![];
+    for (source, entry) in config.dotfiles.iter() {
+        if !machine.applies(entry) || !ctx.tags().matches(entry) {
+            continue;
         }
-        let status_msg = if missings.len() == 0 {
-            "Complete".to_string()
-        } else if missings.len() == 1 {
-            "Missing ".to_string() + missings[0]
+        let abs_link = home.join(&entry.link);
+        let plain_source = project.join(source);
+        let (abs_source, source_state) = match select(&plain_source, &machine) {
+            Some(selected) => (selected, SourceState::Present),
+            None => (plain_source.clone(), SourceState::Missing),
+        };
+        let alternate = if abs_source == plain_source {
+            None
         } else {
-            missings
-                .iter()
-                .enumerate()
-                .fold(String::from("Missing "), |mut s, (i, v)| {
-                    if i == 0 {
-                        s.push_str(v);
-                    } else if i + 1 == missings.len() {
-                        s.push_str(", and ");
-                        s.push_str(v);
-                    } else {
-                        s.push_str(", ");
-                        s.push_str(v);
-                    }
-                    s
-                })
+            let relative = abs_source.strip_prefix(project).unwrap_or(&abs_source);
+            SourcePath::new(relative).ok()
+        };
+        let link_target = fs::read_link(&abs_link).ok();
+        let link_state = entry_state(&abs_link, &abs_source, entry.mode, &vars);
+        dotfiles.push(DotfileStatus {
+            source: source.clone(),
+            link: entry.link.clone(),
+            mode: entry.mode,
+            alternate,
+            source_state,
+            link_state,
+            link_target,
+        });
+    }
+    Ok(ProjectStatus {
+        project: project.clone(),
+        dotfiles,
+    })
+}
+
+/// Classify what is found at `abs_link`, compared to the expected `abs_source`.
+pub fn link_state(abs_link: &Path, abs_source: &Path) -> LinkState {
+    let metadata = match fs::symlink_metadata(abs_link) {
+        Ok(m) => m,
+        Err(_) => return LinkState::Absent,
+    };
+    if metadata.is_symlink() {
+        let target = match fs::read_link(abs_link) {
+            Ok(t) => t,
+            Err(_) => return LinkState::Dangling,
+        };
+        let target = match abs_link.parent() {
+            Some(parent) if target.is_relative() => resolve_path(parent.join(&target)),
+            _ => target,
         };
-        let path_str = source
-            .to_str()
-            .expect("path to be able to be converted to string");
-        println!(" {}", format!("{path_str}: {status_msg}"));
+        if target == abs_source {
+            LinkState::Linked
+        } else if !abs_link.exists() {
+            LinkState::Dangling
+        } else {
+            LinkState::WrongTarget(target)
+        }
+    } else if resolves_to(abs_link, abs_source) {
+        LinkState::LinkedViaParentSymlink
+    } else if metadata.is_dir() {
+        LinkState::OccupiedByDir
+    } else {
+        LinkState::OccupiedByFile
+    }
+}
+
+/// Classify what is found at `abs_link` for a dotfile put in place with `mode`.
+///
+/// Copies are compared to the source by content hash, hard links by inode
+/// and rendered templates to a fresh rendering with `vars`. A symlink to the
+/// source only counts as linked in symlink mode.
+pub fn entry_state(abs_link: &Path, abs_source: &Path, mode: Mode, vars: &Vars) -> LinkState {
+    let state = link_state(abs_link, abs_source);
+    match (mode, &state) {
+        (Mode::Copy, LinkState::OccupiedByFile | LinkState::OccupiedByDir) => {
+            match (content_hash(abs_link), content_hash(abs_source)) {
+                (Ok(link_hash), Ok(source_hash)) if link_hash == source_hash => LinkState::Copied,
+                (_, Ok(_)) => LinkState::CopyDiffers,
+                _ => state,
+            }
+        }
+        (Mode::Hardlink, LinkState::OccupiedByFile) if same_inode(abs_link, abs_source) => {
+            LinkState::Hardlinked
+        }
+        (Mode::Hardlink, LinkState::OccupiedByFile) => LinkState::HardlinkBroken,
+        (Mode::Copy | Mode::Hardlink | Mode::Template, LinkState::Linked) => LinkState::Symlinked,
+        (Mode::Template, LinkState::OccupiedByFile) => {
+            match (render_file(abs_source, vars), fs::read_to_string(abs_link)) {
+                (Ok(rendered), Ok(content)) if rendered == content => LinkState::Rendered,
+                _ => LinkState::RenderStale,
+            }
+        }
+        _ => state,
     }
+}
+
+fn resolves_to(path: &Path, expected: &Path) -> bool {
+    match (fs::canonicalize(path), fs::canonicalize(expected)) {
+        (Ok(p), Ok(e)) => p == e,
+        _ => false,
+    }
+}
+
+pub fn project_summary(ctx: &Context) -> Result<(), StatusError> {
+    let status = project_status(ctx)?;
+    print!("{}", status.to_human());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_file;
+
+    use super::*;
+    use crate::{
+        config::TagFilter,
+        example::{example_complete_setup_from_structure, get_example_structure},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+
+    #[rstest]
+    fn basic_status(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("basic_status")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        assert_eq!(
+            project_status(&ctx).unwrap().outcome(),
+            StatusOutcome::Clean
+        );
+
+        remove_file(f.home.join(&f.bashrc.link)).unwrap();
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.outcome(), StatusOutcome::Drift);
+        let bashrc = &status.dotfiles[0];
+        assert_eq!(bashrc.source, f.bashrc.source);
+        assert_eq!(bashrc.source_state, SourceState::Present);
+        assert_eq!(bashrc.link_state, LinkState::Absent);
+        assert_eq!(bashrc.link_target, None);
+        assert!(status.to_human().contains(" bashrc: Missing link\n"));
+        assert!(status.to_human().contains(" nvim: Complete\n"));
+        let porcelain = status.to_porcelain();
+        assert!(porcelain.contains("\tbashrc\t"));
+        assert!(porcelain.contains("\tpresent\tabsent\t-\n"));
+    }
+
+    #[rstest]
+    fn link_states(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("link_states")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let abs_link = f.home.join(&f.bashrc.link);
+        let abs_source = f.dotfiles.join(&f.bashrc.source);
+        assert_eq!(link_state(&abs_link, &abs_source), LinkState::Linked);
+
+        remove_file(&abs_link).unwrap();
+        fs::write(&abs_link, "replaced").unwrap();
+        assert_eq!(
+            link_state(&abs_link, &abs_source),
+            LinkState::OccupiedByFile
+        );
+
+        remove_file(&abs_link).unwrap();
+        fs::create_dir(&abs_link).unwrap();
+        assert_eq!(link_state(&abs_link, &abs_source), LinkState::OccupiedByDir);
+
+        fs::remove_dir(&abs_link).unwrap();
+        let other = test_dir.join("other");
+        fs::write(&other, "other").unwrap();
+        std::os::unix::fs::symlink(&other, &abs_link).unwrap();
+        assert_eq!(
+            link_state(&abs_link, &abs_source),
+            LinkState::WrongTarget(other.clone())
+        );
+
+        remove_file(&other).unwrap();
+        assert_eq!(link_state(&abs_link, &abs_source), LinkState::Dangling);
+
+        let nvim_link = f.home.join(&f.nvim.link);
+        let nvim_source = f.dotfiles.join(&f.nvim.source);
+        assert_eq!(
+            link_state(&nvim_link.join("init.lua"), &nvim_source.join("init.lua")),
+            LinkState::LinkedViaParentSymlink
+        );
+    }
+
+    #[rstest]
+    fn status_by_tag(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("status_by_tag")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        for (entry, tags) in config.dotfiles.values_mut().zip([["shell"], ["editor"]]) {
+            entry.tags = tags.iter().map(|t| t.to_string()).collect();
+        }
+        config.write(&config_path).unwrap();
+        let with_tags = |tags: TagFilter| {
+            Context::builder()
+                .home(f.home.to_path_buf())
+                .cwd(f.home.to_path_buf())
+                .project(f.dotfiles.to_path_buf())
+                .tags(tags)
+                .build()
+                .unwrap()
+        };
+        remove_file(f.home.join(&f.nvim.link)).unwrap();
+
+        let shell = with_tags(TagFilter {
+            include: vec!["shell".to_string()],
+            ..Default::default()
+        });
+        let status = project_status(&shell).unwrap();
+        assert_eq!(status.dotfiles.len(), 1);
+        assert_eq!(status.dotfiles[0].source, f.bashrc.source);
+        assert!(status.is_clean());
+
+        let no_shell = with_tags(TagFilter {
+            exclude: vec!["shell".to_string()],
+            ..Default::default()
+        });
+        let status = project_status(&no_shell).unwrap();
+        assert_eq!(status.dotfiles.len(), 1);
+        assert_eq!(status.dotfiles[0].source, f.nvim.source);
+        assert_eq!(status.outcome(), StatusOutcome::Drift);
+    }
+}
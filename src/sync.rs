@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{
+    alternate::active_source,
+    config::{self, DotConfig, Mode},
+    context::Context,
+    machine::Machine,
+    plan::{ApplyError, Operation, Plan},
+    status::{entry_state, LinkState},
+    template::Vars,
+    types::SourcePath,
+    CONFIG_FILE_NAME,
+};
+
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("project not initialized")]
+    ProjectNotInitialized,
+    #[error("dotfile not recorded in config")]
+    DotfileNotRecorded,
+    #[error("dotfile is not in copy mode")]
+    NotCopyMode,
+    #[error("dotfile not found")]
+    DotfileNotFound,
+    #[error("no copy found at {0}")]
+    CopyNotFound(PathBuf),
+    #[error("could not read dotman config: {0}")]
+    ReadConfigError(#[from] config::ReadError),
+    #[error("could not sync dotfile: {0}")]
+    Apply(#[from] ApplyError),
+}
+
+/// Which side of a copied dotfile is overwritten by the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncDirection {
+    /// Copy the project source over the copy in the home directory.
+    ToHome,
+    /// Copy the copy in the home directory over the project source.
+    ToProject,
+}
+
+/// Plan bringing every dotfile in copy mode, or only `dotfile`, in sync.
+pub fn plan_sync(
+    ctx: &Context,
+    dotfile: Option<&SourcePath>,
+    direction: SyncDirection,
+) -> Result<Plan, SyncError> {
+    use SyncError as E;
+    let project = ctx.project();
+    let config_path = project.join(CONFIG_FILE_NAME);
+    if !config_path.exists() {
+        return Err(E::ProjectNotInitialized);
+    }
+    let config = DotConfig::from_file(&config_path)?;
+    if let Some(dotfile) = dotfile {
+        match config.dotfiles.get(dotfile) {
+            None => return Err(E::DotfileNotRecorded),
+            Some(entry) if entry.mode != Mode::Copy => return Err(E::NotCopyMode),
+            Some(_) => {}
+        }
+    }
+    let machine = Machine::current(ctx);
+    let mut plan = Plan::journaled(project);
+    for (source, entry) in config.dotfiles.iter() {
+        if entry.mode != Mode::Copy || dotfile.is_some_and(|d| d != source) {
+            continue;
+        }
+        if !machine.applies(entry) {
+            plan.skip(source, "not for this machine");
+            continue;
+        }
+        let abs_source = active_source(ctx, source).unwrap_or_else(|| project.join(source));
+        let abs_link = ctx.home().join(&entry.link);
+        // Copies do not depend on template variables.
+        if entry_state(&abs_link, &abs_source, entry.mode, &Vars::new()) == LinkState::Copied {
+            plan.skip(source, "already in sync");
+            continue;
+        }
+        let (from, to) = match direction {
+            SyncDirection::ToHome if !abs_source.exists() => return Err(E::DotfileNotFound),
+            SyncDirection::ToHome => (abs_source, abs_link),
+            SyncDirection::ToProject if abs_link.is_symlink() || !abs_link.exists() => {
+                return Err(E::CopyNotFound(abs_link));
+            }
+            SyncDirection::ToProject => (abs_link, abs_source),
+        };
+        plan.begin_entry(source);
+        if to.exists() || to.is_symlink() {
+            plan.remove(to.clone());
+        } else {
+            plan.create_parents(&to);
+        }
+        plan.push(Operation::Copy { from, to });
+    }
+    Ok(plan)
+}
+
+pub fn sync(
+    ctx: &Context,
+    dotfile: Option<&SourcePath>,
+    direction: SyncDirection,
+) -> Result<(), SyncError> {
+    plan_sync(ctx, dotfile, direction)?.apply()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Condition, DotEntry},
+        diff::project_diff,
+        example::{example_new_machine_from_structure, get_example_structure},
+        setup,
+        status::project_status,
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+    use std::fs;
+
+    #[rstest]
+    fn copy_mode_sync(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("copy_mode_sync")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        for entry in config.dotfiles.values_mut() {
+            *entry = DotEntry::new(entry.link.clone()).with_mode(Mode::Copy);
+        }
+        config.write(&config_path).unwrap();
+        setup::setup_project(&ctx, Default::default()).unwrap();
+
+        let bashrc = f.home.join(&f.bashrc.link);
+        assert!(!bashrc.is_symlink());
+        assert!(project_status(&ctx).unwrap().is_clean());
+
+        fs::write(&bashrc, "edited at home").unwrap();
+        let status = project_status(&ctx).unwrap();
+        assert_eq!(status.dotfiles[0].link_state, LinkState::CopyDiffers);
+
+        let plan = plan_sync(&ctx, None, SyncDirection::ToProject).unwrap();
+        assert_eq!(plan.skipped().len(), 1);
+        plan.apply().unwrap();
+        let source = f.dotfiles.join(&f.bashrc.source);
+        assert_eq!(fs::read_to_string(&source).unwrap(), "edited at home");
+        assert!(project_status(&ctx).unwrap().is_clean());
+
+        fs::write(&source, "edited in project").unwrap();
+        sync(&ctx, Some(&f.bashrc.source), SyncDirection::ToHome).unwrap();
+        assert_eq!(fs::read_to_string(&bashrc).unwrap(), "edited in project");
+    }
+
+    #[rstest]
+    fn sync_skips_other_machines(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("sync_skips_other_machines")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_new_machine_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let mut config = DotConfig::from_file(&config_path).unwrap();
+        let entry = config.dotfiles.get_mut(&f.bashrc.source).unwrap();
+        entry.mode = Mode::Copy;
+        entry.when = Condition {
+            profiles: vec!["personal".to_string()],
+            ..Default::default()
+        };
+        config.write(&config_path).unwrap();
+
+        let plan = plan_sync(&ctx, None, SyncDirection::ToHome).unwrap();
+        assert!(plan.is_empty());
+        assert_eq!(
+            plan.skipped(),
+            &[(f.bashrc.source.clone(), "not for this machine".to_string())]
+        );
+        sync(&ctx, Some(&f.bashrc.source), SyncDirection::ToHome).unwrap();
+        assert!(!f.home.join(&f.bashrc.link).exists());
+        assert!(project_diff(&ctx, Some(&f.bashrc.source))
+            .unwrap()
+            .is_empty());
+    }
+}
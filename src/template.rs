@@ -0,0 +1,139 @@
+use std::{collections::BTreeMap, env, fs, io, path::Path};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::context::Context;
+
+/// Machine specific template variables, relative to the project root.
+///
+/// Meant to stay out of version control, so every machine can keep its own.
+pub const LOCAL_VARS_FILE_NAME: &str = ".dotman.local.toml";
+
+#[derive(Error, Debug)]
+pub enum VarsError {
+    #[error("could not read local variables: {0}")]
+    Read(#[from] io::Error),
+    #[error("could not parse local variables: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("could not read template: {0}")]
+    Read(#[from] io::Error),
+    #[error("unknown template variable `{0}`")]
+    UnknownVariable(String),
+    #[error("unclosed `{{{{` in template")]
+    Unclosed,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LocalVarsFile {
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+}
+
+/// Variables available to templates, by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Vars(BTreeMap<String, String>);
+
+/// Name of this machine as given by `gethostname(2)`, empty if it cannot be found.
+pub fn hostname() -> String {
+    ::hostname::get()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Name of the current user, empty if it cannot be found.
+pub fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_default()
+}
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The built-in `hostname`, `user` and `os` variables of this machine.
+    pub fn machine() -> Self {
+        let mut vars = Self::new();
+        vars.insert("hostname", hostname());
+        vars.insert("user", user());
+        vars.insert("os", env::consts::OS);
+        vars
+    }
+
+    /// Built-in variables, overridden by the `[vars]` of the project's local variables file.
+    pub fn load(ctx: &Context) -> Result<Self, VarsError> {
+        let mut vars = Self::machine();
+        let path = ctx.project().join(LOCAL_VARS_FILE_NAME);
+        if path.exists() {
+            let local: LocalVarsFile = toml::from_str(&fs::read_to_string(path)?)?;
+            vars.0.extend(local.vars);
+        }
+        Ok(vars)
+    }
+
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) {
+        self.0.insert(name.into(), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+/// Replace every `{{ name }}` in `template` with the value of the variable `name`.
+pub fn render(template: &str, vars: &Vars) -> Result<String, RenderError> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or(RenderError::Unclosed)?;
+        let name = after[..end].trim();
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => return Err(RenderError::UnknownVariable(name.to_string())),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Render the template file at `path`.
+pub fn render_file(path: &Path, vars: &Vars) -> Result<String, RenderError> {
+    render(&fs::read_to_string(path)?, vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut vars = Vars::new();
+        vars.insert("email", "me@example.com");
+        vars.insert("hostname", "laptop");
+        let rendered = render("email = {{ email }}\n# {{hostname}}\n", &vars).unwrap();
+        assert_eq!(rendered, "email = me@example.com\n# laptop\n");
+        assert!(matches!(
+            render("{{ proxy }}", &vars),
+            Err(RenderError::UnknownVariable(name)) if name == "proxy"
+        ));
+        assert!(matches!(
+            render("{{ email", &vars),
+            Err(RenderError::Unclosed)
+        ));
+    }
+
+    #[test]
+    fn test_hostname() {
+        assert!(!hostname().is_empty());
+        assert_eq!(Vars::machine().0["hostname"], hostname());
+    }
+}
@@ -4,10 +4,7 @@ use serde::{
     de::{self, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
 };
-use std::{
-    ops::Deref,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 use crate::utils::resolve_path;
@@ -22,7 +19,7 @@ impl ProjectPath {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ProjectPathError> {
         let path = resolve_path(path);
         if path.is_absolute() {
-            Ok(Self(path.into()))
+            Ok(Self(path))
         } else {
             Err(ProjectPathError)
         }
@@ -47,7 +44,7 @@ impl SourcePath {
         if path.is_absolute() {
             Err(SourcePathError)
         } else {
-            Ok(Self(path.into()))
+            Ok(Self(path))
         }
     }
 }
@@ -77,7 +74,7 @@ impl LinkPath {
         if path.is_absolute() {
             Err(LinkPathError)
         } else {
-            Ok(Self(path.into()))
+            Ok(Self(path))
         }
     }
 }
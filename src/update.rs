@@ -1,11 +1,18 @@
-use std::{fs, io, os::unix::fs as unix_fs, path::Path};
+use std::{fs, path::Path};
 
 use thiserror::Error;
 
 use crate::{
-    config::{self, DotConfig},
+    alternate::active_source,
+    config::{self, DotConfig, DotEntry, Mode},
+    context::Context,
+    machine::Machine,
+    plan::{ApplyError, Plan},
+    setup::{self, SetupError},
+    status::{entry_state, LinkState},
+    template::{Vars, VarsError},
     types::{LinkPath, ProjectPath, SourcePath},
-    utils::AbsPath,
+    utils::resolve_path,
     CONFIG_FILE_NAME,
 };
 
@@ -19,81 +26,196 @@ pub enum UpdateError {
     LinkOccupied,
     #[error("could not read dotman config: {0}")]
     ReadConfigError(#[from] config::ReadError),
+    #[error("{0}")]
+    Vars(#[from] VarsError),
     #[error("Could not serialize config: {0}")]
     ConfigSerializationError(#[from] toml::ser::Error),
-    #[error(
-        "unexpected IO error while updating dotfile, successfully rolled-back changes\n IO-Error: {0}"
-    )]
-    IO(io::Error),
-    #[error(
-        "unexpected IO error while updating dotfile, could not roll-back changes\n io-error: {original_error}\n rollback-error: {rollback_error}"
-    )]
-    RollbackError {
-        original_error: io::Error,
-        rollback_error: io::Error,
-    },
+    #[error("could not link dotfile: {0}")]
+    Link(#[from] SetupError),
+    #[error("could not update dotfile: {0}")]
+    Apply(#[from] ApplyError),
 }
 
-fn raw_update(
-    link: &Path,
-    full_source: &Path,
-    config_path: &Path,
-    config_content: &str,
-) -> Result<(), io::Error> {
-    unix_fs::symlink(&full_source, &link)?;
-    fs::write(config_path, config_content)?;
-    return Ok(());
+/// Whether `link` is a symlink pointing somewhere inside `project`.
+fn points_into(link: &Path, project: &ProjectPath) -> bool {
+    link.is_symlink()
+        && fs::read_link(link)
+            .map(|target| {
+                resolve_path(link.parent().unwrap_or(link).join(target)).starts_with(project)
+            })
+            .unwrap_or(false)
 }
 
-fn rollback_update(link: &Path) -> Result<(), io::Error> {
-    if link.exists() && link.is_symlink() {
-        fs::remove_file(link)?;
-    }
-    Ok(())
-}
-
-fn atomic_update(
-    link: &Path,
-    source: &Path,
-    config: &Path,
-    config_content: &str,
-) -> Result<(), UpdateError> {
-    let result = raw_update(link, source, config, config_content);
-    if let Err(err) = result {
-        if let Err(rollback_error) = rollback_update(link) {
-            return Err(UpdateError::RollbackError {
-                original_error: err,
-                rollback_error,
-            });
-        }
-        return Err(UpdateError::IO(err));
-    }
-    return Ok(());
-}
-
-pub fn update(
-    home: &AbsPath,
+/// Plan moving the link of `source` to `link` and recording it in the config.
+///
+/// The previous link is removed if it points into the project or is an
+/// unchanged copy, and a link already pointing at `source` is left as is.
+/// Links of dotfiles that do not apply to this machine are only recorded.
+pub fn plan_update(
+    ctx: &Context,
     link: &LinkPath,
     source: &SourcePath,
-    project: &ProjectPath,
-) -> Result<(), UpdateError> {
+) -> Result<Plan, UpdateError> {
     use UpdateError as E;
+    let project = ctx.project();
+    let home = ctx.home();
     let config_path = project.join(CONFIG_FILE_NAME);
     if !config_path.exists() {
         return Err(E::ProjectNotInitialized);
     }
-    let abs_source = project.join(source);
-    if !abs_source.exists() {
-        return Err(E::DotfileNotFound);
-    }
+    let abs_source = active_source(ctx, source).ok_or(E::DotfileNotFound)?;
+    let mut config = DotConfig::from_file(&config_path)?;
     let abs_link = home.join(link);
-    if abs_link.is_symlink() || abs_link.exists() {
-        // TODO: Think about what happens if the symlink is there as it should
+    let vars = Vars::load(ctx)?;
+    let old = config.dotfiles.get(source).cloned();
+    let mode = old.as_ref().map_or(Mode::default(), |e| e.mode);
+    let changed = old.as_ref().map(|e| &e.link) != Some(link);
+    let old_link = old.map(|e| home.join(&e.link)).filter(|old| {
+        points_into(old, project)
+            || matches!(
+                entry_state(old, &abs_source, mode, &vars),
+                LinkState::Copied | LinkState::Hardlinked | LinkState::Rendered
+            )
+    });
+    let entry = config
+        .dotfiles
+        .entry(source.clone())
+        .or_insert_with(|| DotEntry::new(link.clone()));
+    entry.link = link.clone();
+    if !Machine::current(ctx).applies(entry) {
+        // Only record the new link, nothing is linked on this machine.
+        let mut plan = Plan::journaled(project);
+        if changed {
+            plan.write_config(&config_path, &config)?;
+        }
+        plan.skip(source, "not for this machine");
+        return Ok(plan);
+    }
+    let linked = matches!(
+        entry_state(&abs_link, &abs_source, mode, &vars),
+        LinkState::Linked | LinkState::Copied | LinkState::Hardlinked | LinkState::Rendered
+    );
+    let replaces_old = old_link.as_deref() == Some(abs_link.as_path());
+    if !linked && !replaces_old && (abs_link.is_symlink() || abs_link.exists()) {
         return Err(E::LinkOccupied);
     }
-    let mut config = DotConfig::from_file(&config_path)?;
-    let _ = config.dotfiles.insert(source.clone(), link.clone());
-    let config_content = config.to_string()?;
-    atomic_update(&abs_link, &abs_source, &config_path, &config_content)?;
+    let mut plan = Plan::journaled(project);
+    if let Some(old_link) = old_link.filter(|old| !linked || *old != abs_link) {
+        plan.remove(old_link);
+    }
+    if !linked {
+        setup::plan_link(&mut plan, &abs_link, &abs_source, mode, &vars)?;
+    }
+    if changed {
+        plan.write_config(&config_path, &config)?;
+    }
+    Ok(plan)
+}
+
+pub fn update(ctx: &Context, link: &LinkPath, source: &SourcePath) -> Result<(), UpdateError> {
+    plan_update(ctx, link, source)?.apply()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        example::{example_complete_setup_from_structure, get_example_structure},
+        tests::root_dir,
+        utils::AbsPath,
+    };
+    use rstest::rstest;
+
+    #[rstest]
+    fn update_retargets_link(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("update_retargets_link")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let new_link = LinkPath::new(".bashrc.d/bashrc").unwrap();
+        update(&ctx, &new_link, &f.bashrc.source).unwrap();
+        assert!(!f.home.join(&f.bashrc.link).is_symlink());
+        assert!(f.home.join(&new_link).is_symlink());
+        let config = DotConfig::from_file(f.dotfiles.join(CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(
+            config.dotfiles.get(&f.bashrc.source).map(|e| &e.link),
+            Some(&new_link)
+        );
+
+        let plan = plan_update(&ctx, &new_link, &f.bashrc.source).unwrap();
+        assert!(plan.is_empty());
+
+        let relative = Path::new("..")
+            .join(f.dotfiles.strip_prefix(&f.home).unwrap())
+            .join(&f.bashrc.source);
+        fs::remove_file(f.home.join(&new_link)).unwrap();
+        std::os::unix::fs::symlink(&relative, f.home.join(&new_link)).unwrap();
+        let other_link = LinkPath::new(".bashrc").unwrap();
+        update(&ctx, &other_link, &f.bashrc.source).unwrap();
+        assert!(!f.home.join(&new_link).is_symlink());
+        assert!(f.home.join(&other_link).is_symlink());
+    }
+
+    #[rstest]
+    fn update_keeps_entry_settings(root_dir: &AbsPath) {
+        let test_dir = AbsPath::new(root_dir.join("update_keeps_entry_settings")).unwrap();
+        let f = get_example_structure(&test_dir, &test_dir, &test_dir);
+        example_complete_setup_from_structure(&f).unwrap();
+        let ctx = f.context();
+        let config_path = f.dotfiles.join(CONFIG_FILE_NAME);
+        let os = std::env::consts::OS;
+        fs::write(
+            &config_path,
+            format!(
+                r#"[dotfiles]
+nvim = "~/config/nvim"
+
+[dotfiles.bashrc]
+link = "~/bashrc"
+tags = ["shell"]
+
+[dotfiles.bashrc.when]
+os = ["{os}"]
+"#
+            ),
+        )
+        .unwrap();
+        let new_link = LinkPath::new(".bashrc2").unwrap();
+        update(&ctx, &new_link, &f.bashrc.source).unwrap();
+        assert!(f.home.join(&new_link).is_symlink());
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            format!(
+                r#"[dotfiles]
+nvim = "~/config/nvim"
+
+[dotfiles.bashrc]
+link = "~/.bashrc2"
+tags = ["shell"]
+
+[dotfiles.bashrc.when]
+os = ["{os}"]
+"#
+            )
+        );
+
+        let config = fs::read_to_string(&config_path).unwrap();
+        fs::write(
+            &config_path,
+            config.replace("os = ", "profiles = [\"personal\"]\nos = "),
+        )
+        .unwrap();
+        let other_link = LinkPath::new(".bashrc3").unwrap();
+        let plan = plan_update(&ctx, &other_link, &f.bashrc.source).unwrap();
+        assert_eq!(
+            plan.skipped(),
+            &[(f.bashrc.source.clone(), "not for this machine".to_string())]
+        );
+        plan.apply().unwrap();
+        assert!(f.home.join(&new_link).is_symlink());
+        assert!(!f.home.join(&other_link).exists());
+        let config = DotConfig::from_file(&config_path).unwrap();
+        assert_eq!(config.dotfiles[&f.bashrc.source].link, other_link);
+    }
+}
@@ -1,6 +1,9 @@
 use std::{
     fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
     ops::Deref,
+    os::unix::fs::{self as unix_fs, MetadataExt},
     path::{Path, PathBuf},
 };
 use thiserror::Error;
@@ -17,7 +20,7 @@ impl AbsPath {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, AbsPathError> {
         let path = resolve_path(path);
         if path.is_absolute() {
-            Ok(Self(path.into()))
+            Ok(Self(path))
         } else {
             Err(AbsPathError)
         }
@@ -59,7 +62,7 @@ impl RelPath {
         if path.is_absolute() {
             Err(RelPathError)
         } else {
-            Ok(Self(path.into()))
+            Ok(Self(path))
         }
     }
 }
@@ -102,7 +105,7 @@ pub fn resolve_path<P: AsRef<Path>>(path: P) -> PathBuf {
             }
         }
     }
-    return path_buff;
+    path_buff
 }
 
 pub fn normalize_path<P: AsRef<Path>, H: AsRef<Path>, W: AsRef<Path>>(
@@ -136,7 +139,82 @@ pub fn normalize_path<P: AsRef<Path>, H: AsRef<Path>, W: AsRef<Path>>(
     };
     let end_path: PathBuf = comp_iter.collect();
     let path = base_path.join(end_path);
-    return resolve_path(path);
+    resolve_path(path)
+}
+
+/// Remove a file, symlink or directory tree.
+pub fn remove_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if path.is_symlink() || !path.is_dir() {
+        fs::remove_file(path)
+    } else {
+        fs::remove_dir_all(path)
+    }
+}
+
+/// Copy a file or directory tree from `from` to `to`.
+///
+/// Permissions are preserved, and symlinks inside the tree are recreated
+/// rather than followed. `to` must not exist.
+pub fn copy_all<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        unix_fs::symlink(fs::read_link(from)?, to)?;
+    } else if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(entry.path(), to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        if to.exists() || to.is_symlink() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b` are the same file, hard links to one inode.
+pub fn same_inode<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Hash of the content of a file or directory tree.
+///
+/// Directory entries are hashed in name order together with their relative
+/// paths, symlinks by their target rather than what they point at.
+pub fn content_hash<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    fn hash_into(path: &Path, hasher: &mut DefaultHasher) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_symlink() {
+            "symlink".hash(hasher);
+            fs::read_link(path)?.hash(hasher);
+        } else if metadata.is_dir() {
+            "dir".hash(hasher);
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            entries.sort();
+            for name in entries {
+                name.hash(hasher);
+                hash_into(&path.join(name), hasher)?;
+            }
+        } else {
+            "file".hash(hasher);
+            fs::read(path)?.hash(hasher);
+        }
+        Ok(())
+    }
+    let mut hasher = DefaultHasher::new();
+    hash_into(path.as_ref(), &mut hasher)?;
+    Ok(hasher.finish())
 }
 
 fn filename<P: AsRef<Path>>(path: P) -> String {
@@ -153,7 +231,7 @@ pub fn find_dotman_projects(base_dir: &AbsPath) -> Vec<ProjectPath> {
     while let Some(current_dir) = to_visit.pop() {
         let dir_iter: fs::ReadDir = match fs::read_dir(current_dir) {
             Ok(res) => res,
-            Err(_) => return vec![],
+            Err(_) => continue,
         };
         for entry in dir_iter {
             let entry = match entry {
@@ -179,7 +257,7 @@ pub fn find_dotman_projects(base_dir: &AbsPath) -> Vec<ProjectPath> {
             }
         }
     }
-    return projects;
+    projects
 }
 
 #[cfg(test)]